@@ -1,51 +1,137 @@
-use std::{ collections::HashMap, error::Error };
-use reqwest::Client;
+use std::{ cmp::Ordering, collections::HashMap, env, error::Error, fs, path::{ Path, PathBuf }, time::Duration };
+use chrono::{ DateTime, Utc };
+use log::warn;
+use regex::Regex;
+use reqwest::{ Client, StatusCode };
 use serde::{ Deserialize, Serialize };
 use serde_json::Value;
 
-use crate::Artifact;
+use crate::{ download_utils::metadata_client, forge_client_install::ForgeInstallError, join_url, Artifact, Repositories };
 
-const PROMOTIONS_URL: &str = "https://files.minecraftforge.net/net/minecraftforge/forge/promotions_slim.json";
-const METADATA_URL: &str = "https://files.minecraftforge.net/net/minecraftforge/forge/maven-metadata.json";
+const PROMOTIONS_PATH: &str = "net/minecraftforge/forge/promotions_slim.json";
+const METADATA_PATH: &str = "net/minecraftforge/forge/maven-metadata.json";
+const NEOFORGE_METADATA_PATH: &str = "net/neoforged/neoforge/maven-metadata.xml";
+
+/// Forge and NeoForge are published to different mavens under different coordinates
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Loader {
+  #[default]
+  Forge,
+  NeoForge,
+}
+
+impl Loader {
+  pub fn get_group(&self) -> &'static str {
+    match self {
+      Loader::Forge => "net.minecraftforge",
+      Loader::NeoForge => "net.neoforged",
+    }
+  }
+
+  pub fn get_artifact_id(&self) -> &'static str {
+    match self {
+      Loader::Forge => "forge",
+      Loader::NeoForge => "neoforge",
+    }
+  }
+
+  pub fn get_maven<'a>(&self, repositories: &'a Repositories) -> &'a str {
+    match self {
+      Loader::Forge => &repositories.forge_maven,
+      Loader::NeoForge => &repositories.neoforge_maven,
+    }
+  }
+
+  /// Where [`ForgeVersionHandler::new`] caches the versions of this loader
+  pub fn get_default_cache_path(&self) -> PathBuf {
+    env::temp_dir().join("forge_downloader").join(format!("{}_versions.json", self.get_artifact_id()))
+  }
+}
+
+/// Which versions [`ForgeVersionHandler::get_best_version_for_channel`] may pick
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Channel {
+  /// Recommended versions, or else the newest version that isn't a beta
+  #[default]
+  Stable,
+  /// The newest version, betas included
+  Beta,
+}
 
 pub struct ForgeVersionHandler {
+  pub loader: Loader,
   pub versions: Vec<ForgeVersionInfo>,
 }
 
 impl ForgeVersionHandler {
-  pub async fn new() -> Result<Self, Box<dyn Error>> {
-    let promotions = get_promoted_versions().await?;
-
-    let mut versions = vec![];
-    for (mc_ver, forge_versions) in list_forge_versions().await? {
-      let recommended = promotions.get(&format!("{mc_ver}-recommended"));
-      let latest = promotions.get(&format!("{mc_ver}-latest"));
-
-      for full_forge_ver in forge_versions {
-        let forge_ver = full_forge_ver.split_once("-").unwrap().1;
-
-        let (forge_ver, suffix) = match forge_ver.split_once("-") {
-          Some(parts) => (parts.0, Some(parts.1)),
-          None => (forge_ver, None),
-        };
-
-        let recommended = recommended.is_some_and(|ver| ver == forge_ver);
-        let latest = latest.is_some_and(|ver| ver == forge_ver);
-        versions.push(ForgeVersionInfo {
-          mc_version: mc_ver.clone(),
-          forge_version: forge_ver.to_string(),
-          suffix: suffix.map(|s| s.to_string()),
-          latest,
-          recommended,
-        });
+  /// Always fetches the versions, caching them at [`Loader::get_default_cache_path`]. If fetching fails,
+  /// the last cached versions are used.
+  pub async fn new(loader: Loader) -> Result<Self, Box<dyn Error>> {
+    Self::new_cached(loader, &loader.get_default_cache_path(), Duration::ZERO).await
+  }
+
+  pub async fn with_repositories(loader: Loader, repositories: &Repositories) -> Result<Self, Box<dyn Error>> {
+    let versions = match loader {
+      Loader::Forge => fetch_forge_versions(repositories).await?,
+      Loader::NeoForge => fetch_neoforge_versions(repositories).await?,
+    };
+    Ok(Self { loader, versions })
+  }
+
+  /// Reuses the versions saved at `cache_path` while they're younger than `ttl`, fetching and saving them
+  /// otherwise. If fetching fails, an expired cache is still used rather than failing.
+  pub async fn new_cached(loader: Loader, cache_path: &Path, ttl: Duration) -> Result<Self, Box<dyn Error>> {
+    let cached = read_cached_versions(loader, cache_path);
+    if let Some(cached) = &cached {
+      let age = Utc::now().signed_duration_since(cached.fetched_at).to_std().unwrap_or_default();
+      if age < ttl {
+        return Ok(Self { loader, versions: cached.versions.clone() });
       }
     }
-    Ok(Self { versions })
+    match Self::with_repositories(loader, &Repositories::default()).await {
+      Ok(handler) => {
+        let cached = CachedVersions { fetched_at: Utc::now(), loader, versions: handler.versions.clone() };
+        if let Err(err) = write_cached_versions(cache_path, &cached) {
+          warn!("Failed to cache versions at {}: {err}", cache_path.display());
+        }
+        Ok(handler)
+      }
+      Err(err) =>
+        match cached {
+          Some(cached) => {
+            warn!("Failed to fetch versions, using the expired cache at {}: {err}", cache_path.display());
+            Ok(Self { loader, versions: cached.versions })
+          }
+          None => Err(err),
+        }
+    }
+  }
+
+  /// Only reads the versions cached by [`Self::new_cached`], whatever their age, never touching the network
+  pub fn new_offline(loader: Loader, cache_path: &Path) -> Result<Self, Box<dyn Error>> {
+    match read_cached_versions(loader, cache_path) {
+      Some(cached) => Ok(Self { loader, versions: cached.versions }),
+      None => Err(format!("No cached {loader:?} versions at {}", cache_path.display()))?,
+    }
   }
 
   pub fn get_best_version(&self, mc_ver: &str) -> Option<&ForgeVersionInfo> {
-    let mut versions = self.get_by_mc_version(mc_ver).into_iter();
-    versions.find(|v| v.recommended).or(versions.find(|v| v.latest))
+    let versions = self.get_by_mc_version(mc_ver);
+    let recommended = versions.iter().find(|v| v.recommended);
+    recommended.or(versions.iter().find(|v| v.latest)).copied()
+  }
+
+  /// Like [`Self::get_best_version`], but only picks a beta (e.g. NeoForge's first versions for a new
+  /// minecraft version) when asked for [`Channel::Beta`]
+  pub fn get_best_version_for_channel(&self, mc_ver: &str, channel: Channel) -> Option<&ForgeVersionInfo> {
+    let versions = self.get_by_mc_version(mc_ver);
+    match channel {
+      Channel::Stable => {
+        let recommended = versions.iter().find(|v| v.recommended).copied();
+        recommended.or(get_newest_version(versions.into_iter().filter(|v| !v.is_beta())))
+      }
+      Channel::Beta => get_newest_version(versions),
+    }
   }
 
   pub fn get_by_mc_version(&self, mc_ver: &str) -> Vec<&ForgeVersionInfo> {
@@ -55,6 +141,32 @@ impl ForgeVersionHandler {
       .collect()
   }
 
+  /// Versions for every minecraft version starting with `prefix`, "1.20" (or "1.20.x") matches 1.20 and
+  /// 1.20.1 but not 1.2
+  pub fn get_by_mc_version_prefix(&self, prefix: &str) -> Vec<&ForgeVersionInfo> {
+    let prefix = prefix.trim_end_matches(".x");
+    self.versions
+      .iter()
+      .filter(|v| v.mc_version == prefix || v.mc_version.starts_with(&format!("{prefix}.")))
+      .collect()
+  }
+
+  /// Every minecraft version with at least one loader version, oldest first
+  pub fn get_all_mc_versions(&self) -> Vec<String> {
+    let mut mc_versions: Vec<String> = vec![];
+    for version in &self.versions {
+      if !mc_versions.contains(&version.mc_version) {
+        mc_versions.push(version.mc_version.clone());
+      }
+    }
+    mc_versions.sort_by(|a, b| compare_mc_versions(a, b));
+    mc_versions
+  }
+
+  pub fn get_latest_mc_version(&self) -> Option<String> {
+    self.get_all_mc_versions().pop()
+  }
+
   pub fn get_by_forge_version(&self, forge_ver: &str) -> Option<&ForgeVersionInfo> {
     self.versions.iter().find(|v| v.forge_version == forge_ver)
   }
@@ -69,6 +181,8 @@ impl ForgeVersionHandler {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ForgeVersionInfo {
+  #[serde(default)]
+  pub loader: Loader,
   pub mc_version: String,
   pub forge_version: String,
   pub suffix: Option<String>,
@@ -77,8 +191,17 @@ pub struct ForgeVersionInfo {
 }
 
 impl ForgeVersionInfo {
+  pub fn is_beta(&self) -> bool {
+    self.suffix.as_deref().is_some_and(|suffix| suffix.contains("beta"))
+  }
+
   pub fn get_full_version(&self) -> String {
-    let mut parts: Vec<&str> = vec![&self.mc_version, &self.forge_version];
+    let mut parts: Vec<&str> = vec![];
+    // NeoForge versions already encode the minecraft version
+    if self.loader == Loader::Forge {
+      parts.push(&self.mc_version);
+    }
+    parts.push(&self.forge_version);
     if let Some(suffix) = &self.suffix {
       parts.push(suffix);
     }
@@ -86,29 +209,199 @@ impl ForgeVersionInfo {
   }
 
   pub fn get_artifact(&self) -> Artifact {
-    let path = format!("net.minecraftforge:forge:{}:installer", self.get_full_version());
+    let path = format!("{}:{}:{}:installer", self.loader.get_group(), self.loader.get_artifact_id(), self.get_full_version());
     Artifact::try_from(path).unwrap()
   }
 
   pub fn get_installer_url(&self) -> String {
-    let path = self.get_artifact().get_path_string();
-    format!("https://maven.minecraftforge.net/{path}")
+    self.get_installer_url_from(&Repositories::default())
+  }
+
+  pub fn get_installer_url_from(&self, repositories: &Repositories) -> String {
+    join_url(self.loader.get_maven(repositories), &self.get_artifact().get_path_string())
   }
+
+  /// Downloads the installer jar of this version for either loader, see [`Self::download_installer_from`]
+  pub async fn download_installer(&self, client: &Client) -> Result<Vec<u8>, Box<dyn Error>> {
+    self.download_installer_from(client, &Repositories::default()).await
+  }
+
+  /// Fails with [`ForgeInstallError::InstallerNotFound`] when the maven doesn't have this version
+  pub async fn download_installer_from(&self, client: &Client, repositories: &Repositories) -> Result<Vec<u8>, Box<dyn Error>> {
+    let url = self.get_installer_url_from(repositories);
+    let response = client.get(&url).headers(repositories.get_headers(&url)).send().await?;
+    match response.status() {
+      StatusCode::NOT_FOUND => Err(ForgeInstallError::InstallerNotFound(url))?,
+      status if !status.is_success() => Err(ForgeInstallError::Status(url, status.as_u16()))?,
+      _ => Ok(response.bytes().await?.to_vec()),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedVersions {
+  fetched_at: DateTime<Utc>,
+  loader: Loader,
+  versions: Vec<ForgeVersionInfo>,
+}
+
+fn read_cached_versions(loader: Loader, cache_path: &Path) -> Option<CachedVersions> {
+  let cached: CachedVersions = serde_json::from_slice(&fs::read(cache_path).ok()?).ok()?;
+  (cached.loader == loader).then_some(cached)
+}
+
+fn write_cached_versions(cache_path: &Path, cached: &CachedVersions) -> Result<(), Box<dyn Error>> {
+  if let Some(parent) = cache_path.parent() {
+    fs::create_dir_all(parent)?;
+  }
+  fs::write(cache_path, serde_json::to_vec(cached)?)?;
+  Ok(())
+}
+
+async fn fetch_forge_versions(repositories: &Repositories) -> Result<Vec<ForgeVersionInfo>, Box<dyn Error>> {
+  let promotions = get_promoted_versions(repositories).await?;
+
+  let mut versions = vec![];
+  for (mc_ver, forge_versions) in list_forge_versions(repositories).await? {
+    let recommended = promotions.get(&format!("{mc_ver}-recommended"));
+    let latest = promotions.get(&format!("{mc_ver}-latest"));
+
+    for full_forge_ver in forge_versions {
+      let Some((_, forge_ver)) = full_forge_ver.split_once("-") else {
+        continue;
+      };
+
+      let (forge_ver, suffix) = match forge_ver.split_once("-") {
+        Some(parts) => (parts.0, Some(parts.1)),
+        None => (forge_ver, None),
+      };
+
+      let recommended = recommended.is_some_and(|ver| ver == forge_ver);
+      let latest = latest.is_some_and(|ver| ver == forge_ver);
+      versions.push(ForgeVersionInfo {
+        loader: Loader::Forge,
+        mc_version: mc_ver.clone(),
+        forge_version: forge_ver.to_string(),
+        suffix: suffix.map(|s| s.to_string()),
+        latest,
+        recommended,
+      });
+    }
+  }
+  Ok(versions)
+}
+
+async fn fetch_neoforge_versions(repositories: &Repositories) -> Result<Vec<ForgeVersionInfo>, Box<dyn Error>> {
+  let url = join_url(&repositories.neoforge_maven, NEOFORGE_METADATA_PATH);
+  let metadata = metadata_client().get(&url).headers(repositories.get_headers(&url)).send().await?.text().await?;
+  let regex = Regex::new(r"<version>([^<]+)</version>").unwrap();
+
+  let mut versions = regex
+    .captures_iter(&metadata)
+    .filter_map(|captures| parse_neoforge_version(&captures[1]))
+    .collect::<Vec<_>>();
+  build_promoted_versions(&mut versions);
+  Ok(versions)
+}
+
+fn get_newest_version<'a>(versions: impl IntoIterator<Item = &'a ForgeVersionInfo>) -> Option<&'a ForgeVersionInfo> {
+  versions.into_iter().max_by(|a, b| compare_neo_versions(&a.forge_version, &b.forge_version))
+}
+
+/// NeoForge has no promotions file: for each minecraft version the newest version is flagged as latest
+/// and the newest one that isn't a beta as recommended. Versions compare numerically, 20.4.237 is newer
+/// than 20.4.99.
+pub fn build_promoted_versions(versions: &mut [ForgeVersionInfo]) {
+  let mut latest: HashMap<String, String> = HashMap::new();
+  let mut recommended: HashMap<String, String> = HashMap::new();
+  for version in versions.iter() {
+    let mut promotions = vec![&mut latest];
+    if !version.is_beta() {
+      promotions.push(&mut recommended);
+    }
+    for promoted in promotions {
+      let newer = promoted.get(&version.mc_version).is_none_or(|other| compare_neo_versions(&version.forge_version, other).is_gt());
+      if newer {
+        promoted.insert(version.mc_version.clone(), version.forge_version.clone());
+      }
+    }
+  }
+  for version in versions.iter_mut() {
+    version.latest = latest.get(&version.mc_version) == Some(&version.forge_version);
+    version.recommended = recommended.get(&version.mc_version) == Some(&version.forge_version);
+  }
+}
+
+/// "20.4.80-beta" -> minecraft 1.20.4, "21.0.1" -> minecraft 1.21
+pub fn parse_neoforge_version(version: &str) -> Option<ForgeVersionInfo> {
+  let (neoforge_ver, suffix) = match version.split_once("-") {
+    Some(parts) => (parts.0, Some(parts.1)),
+    None => (version, None),
+  };
+  let mut parts = neoforge_ver.split('.');
+  let major: u32 = parts.next()?.parse().ok()?;
+  let minor: u32 = parts.next()?.parse().ok()?;
+  let mc_version = if minor == 0 { format!("1.{major}") } else { format!("1.{major}.{minor}") };
+
+  Some(ForgeVersionInfo {
+    loader: Loader::NeoForge,
+    mc_version,
+    forge_version: neoforge_ver.to_string(),
+    suffix: suffix.map(|s| s.to_string()),
+    latest: true,
+    recommended: false,
+  })
+}
+
+/// Compares loader versions like "20.4.200" by their dotted numeric components, so 20.4.200 is newer than
+/// 20.4.99. A suffix such as "-beta" is ignored.
+pub fn compare_neo_versions(a: &str, b: &str) -> Ordering {
+  let key = |version: &str| -> Vec<u32> {
+    let version = version.split_once('-').map_or(version, |(version, _)| version);
+    version
+      .split('.')
+      .map(|part| part.parse().unwrap_or(0))
+      .collect()
+  };
+  key(a).cmp(&key(b))
+}
+
+/// Compares versions component by component, numerically when they start with a number. Never panics on
+/// odd versions like "1.7.10_pre4", which sorts after 1.7.10 because of its suffix.
+pub fn compare_mc_versions(a: &str, b: &str) -> Ordering {
+  let key = |version: &str| -> Vec<(u32, String)> {
+    version
+      .split('.')
+      .map(|part| {
+        let digits = part.chars().take_while(char::is_ascii_digit).collect::<String>();
+        (digits.parse().unwrap_or(0), part[digits.len()..].to_string())
+      })
+      .collect()
+  };
+  key(a).cmp(&key(b))
 }
 
 // [mc_ver]: "{mc_ver}-{forge_ver}"
-pub async fn list_forge_versions() -> Result<HashMap<String, Vec<String>>, reqwest::Error> {
-  Client::new().get(METADATA_URL).send().await?.json().await
+pub async fn list_forge_versions(repositories: &Repositories) -> Result<HashMap<String, Vec<String>>, reqwest::Error> {
+  let url = join_url(&repositories.forge_files, METADATA_PATH);
+  metadata_client().get(&url).headers(repositories.get_headers(&url)).send().await?.json().await
 }
 
 // "{mc_ver}-latest": "{forge_ver}"
-pub async fn get_promoted_versions() -> Result<HashMap<String, String>, Box<dyn Error>> {
-  let result: Value = Client::new().get(PROMOTIONS_URL).send().await?.json().await?;
+pub async fn get_promoted_versions(repositories: &Repositories) -> Result<HashMap<String, String>, Box<dyn Error>> {
+  let url = join_url(&repositories.forge_files, PROMOTIONS_PATH);
+  let result: Value = metadata_client().get(&url).headers(repositories.get_headers(&url)).send().await?.json().await?;
+  Ok(parse_promotions(&result))
+}
 
-  let mut promos = HashMap::new();
-  for (mc_version, forge_version) in result["promos"].as_object().unwrap() {
-    let forge_version = forge_version.as_str().unwrap().to_string();
-    promos.insert(mc_version.clone(), forge_version);
-  }
-  Ok(promos)
-}
\ No newline at end of file
+/// Malformed entries are skipped, promos may also reference versions missing from the metadata
+pub fn parse_promotions(promotions: &Value) -> HashMap<String, String> {
+  let Some(promos) = promotions["promos"].as_object() else {
+    return HashMap::new();
+  };
+  promos
+    .iter()
+    .filter_map(|(mc_version, forge_version)| Some((mc_version.clone(), forge_version.as_str()?.to_string())))
+    .collect()
+}
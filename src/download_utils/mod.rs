@@ -1,47 +1,348 @@
 pub mod forge;
 
-use std::{ error::Error, fs::{ self, create_dir_all, File }, io::{ self, ErrorKind, Read, Seek, Write }, path::PathBuf };
+use std::{
+  collections::{ HashMap, HashSet },
+  error::Error,
+  fmt::Debug,
+  fs::{ self, create_dir_all, File, OpenOptions },
+  io::{ self, ErrorKind, Read, Seek, Write },
+  path::{ Path, PathBuf },
+  sync::{ Arc, Mutex },
+  time::Duration,
+};
 
-use futures::StreamExt;
+use futures::{ stream, StreamExt };
 use log::{info, warn, error, debug};
-use reqwest::{ Client, Url };
-use sha1::{ Digest, Sha1 };
-use zip::{ result::ZipError, ZipArchive };
+use reqwest::{ header::{ ACCEPT_ENCODING, RANGE }, Client, StatusCode, Url };
+use tokio::sync::{ OwnedSemaphorePermit, Semaphore };
 
 use crate::{
-  forge_client_install::ForgeInstallError,
-  forge_err,
-  forge_installer_profile::{ v1::ForgeLibrary, v2::{ MojangArtifact, MojangLibrary } },
+  forge_client_install::{ CancellationToken, ForgeInstallError, PlannedAction },
+  forge_installer_profile::{ source::ForgeInstallProfileSource, v1::ForgeLibrary, v2::{ MojangArtifact, MojangLibrary, TargetArch, TargetOs } },
+  join_url,
   Artifact,
+  Checksum,
+  ChecksumHasher,
+  ManifestCache,
+  Repositories,
   Sha1Sum,
 };
 
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+const VERIFY_CONCURRENCY: usize = 16;
+
+/// Called while a file downloads with its url, the bytes written so far and its total size, when known
+#[derive(Clone)]
+pub struct ProgressCallback(pub Arc<ProgressFn>);
+
+type ProgressFn = dyn Fn(&str, u64, Option<u64>) + Send + Sync;
+
+impl ProgressCallback {
+  pub fn new(callback: impl Fn(&str, u64, Option<u64>) + Send + Sync + 'static) -> Self {
+    Self(Arc::new(callback))
+  }
+}
+
+impl Debug for ProgressCallback {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "ProgressCallback")
+  }
+}
+
+/// What happened to a library of a V1 install
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibraryOutcome {
+  /// Already present with a valid checksum
+  Valid,
+  /// Downloaded or extracted from the installer
+  Grabbed,
+  /// Couldn't be placed, the install fails
+  Bad,
+  /// Couldn't be placed, but it's left for the Mojang launcher to download
+  Unavailable,
+  /// Disabled or for the other side
+  Skipped,
+}
+
+/// Called once per library of a V1 install with the library, its 1-based position, the library count
+/// and its outcome
+#[derive(Clone)]
+pub struct LibraryCallback(pub Arc<LibraryFn>);
+
+type LibraryFn = dyn Fn(&Artifact, usize, usize, LibraryOutcome) + Send + Sync;
+
+impl LibraryCallback {
+  pub fn new(callback: impl Fn(&Artifact, usize, usize, LibraryOutcome) + Send + Sync + 'static) -> Self {
+    Self(Arc::new(callback))
+  }
+}
+
+impl Debug for LibraryCallback {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "LibraryCallback")
+  }
+}
+
+/// Caps the downloads in flight to a single host. Clones share their slots, so every clone of a
+/// [`DownloadConfig`] counts against the same limit.
+#[derive(Debug, Clone, Default)]
+pub struct HostLimiter(Arc<Mutex<HashMap<String, Arc<Semaphore>>>>);
+
+impl HostLimiter {
+  /// Waits for a free slot on the host of `url`, held until the permit is dropped. The `limit` of a host
+  /// is fixed by its first request. Urls without a host aren't limited.
+  pub async fn acquire(&self, url: &str, limit: usize) -> Option<OwnedSemaphorePermit> {
+    let host = Url::parse(url).ok()?.host_str()?.to_string();
+    let semaphore = {
+      let mut hosts = self.0.lock().unwrap();
+      hosts.entry(host).or_insert_with(|| Arc::new(Semaphore::new(limit.max(1)))).clone()
+    };
+    semaphore.acquire_owned().await.ok()
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadConfig {
+  /// Maximum number of HTTP library downloads in flight at once.
+  pub max_concurrent_downloads: usize,
+  /// Maximum number of downloads in flight to a single host, unlimited when `None`. Downloads from
+  /// different hosts (e.g. mirrors) still run in parallel up to `max_concurrent_downloads`.
+  pub max_concurrent_per_host: Option<usize>,
+  pub host_limiter: HostLimiter,
+  /// How many times a failed download is retried before giving up.
+  pub retries: u32,
+  /// Delay before the first retry, doubled on every following attempt.
+  pub retry_base_delay: Duration,
+  /// Time allowed to establish a connection.
+  pub connect_timeout: Duration,
+  /// Time allowed until the response headers arrive. The body of a download isn't covered, so large
+  /// files aren't cut off.
+  pub request_timeout: Duration,
+  /// Maximum time without receiving a single chunk while streaming a download.
+  pub read_timeout: Duration,
+  /// Base urls tried in order before the canonical url. Only the host (and an optional path prefix)
+  /// is replaced, the artifact path stays the same.
+  pub mirrors: Vec<String>,
+  /// Os whose native libraries get downloaded
+  pub target_os: TargetOs,
+  /// Architecture whose native libraries get downloaded, matched against the library rules too
+  pub target_arch: TargetArch,
+  pub manifest_cache: ManifestCache,
+  pub repositories: Repositories,
+  /// Reports the progress of every download. The total is the size the profile or the version json declares
+  /// for the file, so the totals of every library add up, or the `Content-Length` when it declares none.
+  pub on_progress: Option<ProgressCallback>,
+  /// Reports the outcome of every library of a V1 install
+  pub on_library: Option<LibraryCallback>,
+  /// Client used instead of building one from the timeouts, so callers can share their own
+  pub client: Option<Client>,
+  /// Library folders of other instances, libraries found there with a matching checksum are copied
+  /// instead of downloaded
+  pub additional_library_dirs: Vec<PathBuf>,
+  /// V1 client libraries from the Mojang repository that fail to download are normally left for the
+  /// Mojang launcher. Set this when nothing else will download them, so they fail the install instead.
+  pub treat_unmirrored_mojang_failure_as_fatal: bool,
+  /// Fails every download with [`ForgeInstallError::OfflineMissing`], so only local files are used
+  pub offline: bool,
+  /// Vanilla version json used instead of fetching it from Mojang's manifest when its `id` matches,
+  /// e.g. one the launcher already has in memory or with a custom client download url and sha1
+  pub vanilla_version_json: Option<serde_json::Value>,
+  /// Download urls keyed by artifact descriptor (e.g. `org.ow2.asm:asm:9.5`), used instead of the url of
+  /// the profile or the libraries repository for libraries that moved
+  pub url_overrides: HashMap<String, String>,
+}
+
+impl Default for DownloadConfig {
+  fn default() -> Self {
+    Self {
+      max_concurrent_downloads: 8,
+      max_concurrent_per_host: None,
+      host_limiter: HostLimiter::default(),
+      retries: 0,
+      retry_base_delay: Duration::from_millis(500),
+      connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+      request_timeout: DEFAULT_REQUEST_TIMEOUT,
+      read_timeout: DEFAULT_READ_TIMEOUT,
+      mirrors: vec![],
+      target_os: TargetOs::current(),
+      target_arch: TargetArch::current(),
+      manifest_cache: ManifestCache::default(),
+      repositories: Repositories::default(),
+      on_progress: None,
+      on_library: None,
+      client: None,
+      additional_library_dirs: vec![],
+      treat_unmirrored_mojang_failure_as_fatal: false,
+      offline: false,
+      vanilla_version_json: None,
+      url_overrides: HashMap::new(),
+    }
+  }
+}
+
+impl DownloadConfig {
+  /// Client used for streaming downloads, only the connection is bounded here since the body is
+  /// guarded by `read_timeout` chunk by chunk.
+  pub fn build_client(&self) -> Result<Client, reqwest::Error> {
+    if let Some(client) = &self.client {
+      return Ok(client.clone());
+    }
+    Client::builder().connect_timeout(self.connect_timeout).build()
+  }
+
+  /// Url set in [`Self::url_overrides`] for `artifact`, if any
+  pub fn get_url_override(&self, artifact: &Artifact) -> Option<&String> {
+    self.url_overrides.get(&artifact.get_descriptor())
+  }
+
+  /// `download` with its url replaced by the override of `artifact`
+  pub(crate) fn override_download(&self, artifact: &Artifact, mut download: MojangArtifact) -> MojangArtifact {
+    if let Some(url) = self.get_url_override(artifact) {
+      info!("  Using overridden url for {artifact}");
+      download.url = Some(url.clone());
+    }
+    download
+  }
+}
+
+/// Client for small metadata requests (manifests, version jsons), where a total timeout is fine
+pub(crate) fn metadata_client() -> Client {
+  Client::builder().connect_timeout(DEFAULT_CONNECT_TIMEOUT).timeout(DEFAULT_REQUEST_TIMEOUT).build().unwrap_or_default()
+}
+
 // V2 download
+/// Places the main artifact and every applicable classifier of `library`, each validated on its own.
+/// Returns the artifacts that had to be downloaded, the others were already valid, extracted from the
+/// installer or copied from an additional library directory.
 pub async fn download_library(
-  zip_archive: &mut ZipArchive<impl Read + Seek>,
+  source: &mut ForgeInstallProfileSource<impl Read + Seek>,
   library: &MojangLibrary,
-  root: &PathBuf,
-  optional: fn(&str) -> bool,
-  grabbed: &mut Vec<Artifact>,
-  additional_library_dirs: &Vec<&PathBuf>
-) -> Result<(), Box<dyn Error>> {
-  let artifact = &library.name;
-  let target = artifact.get_local_path(root);
-
-  let download = library.downloads.artifact.as_ref().cloned().unwrap_or(MojangArtifact::new(artifact.get_path_string()));
+  root: &Path,
+  optional: &dyn Fn(&str) -> bool,
+  grabbed: &mut HashSet<Artifact>,
+  additional_library_dirs: &Vec<&PathBuf>,
+  config: &DownloadConfig
+) -> Result<HashSet<Artifact>, Box<dyn Error>> {
+  let pending = resolve_library(source, library, root, optional, grabbed, additional_library_dirs, config).await?;
+  if let Some((artifact, _, _)) = pending.first().filter(|_| config.offline) {
+    Err(ForgeInstallError::OfflineMissing(artifact.get_descriptor()))?;
+  }
+  let mut downloaded = HashSet::new();
+  for (artifact, download, target) in pending {
+    let download = config.override_download(&artifact, download);
+    if let Err(err) = download_library_artifact(&download, &target, config).await {
+      return Err(Box::new(io::Error::new(ErrorKind::Other, format!("Failed to download library {}: {err}", artifact.get_descriptor()))));
+    }
+    grabbed.insert(artifact.clone());
+    downloaded.insert(artifact);
+  }
+  Ok(downloaded)
+}
 
+/// Does every step of a library install that doesn't need the network: validating an existing file,
+/// extracting it from the installer archive or copying it from an additional library directory.
+///
+/// Returns the artifacts (the library itself and its native for the target, if any) that still have to be
+/// fetched with [`download_library_artifact`]. This is kept separate so the installer source (which isn't `Sync`) is only ever
+/// touched serially while the HTTP downloads can run concurrently. Existing files are hashed with
+/// [`Sha1Sum::from_file_async`].
+pub async fn resolve_library(
+  source: &mut ForgeInstallProfileSource<impl Read + Seek>,
+  library: &MojangLibrary,
+  root: &Path,
+  optional: &dyn Fn(&str) -> bool,
+  grabbed: &mut HashSet<Artifact>,
+  additional_library_dirs: &Vec<&PathBuf>,
+  config: &DownloadConfig
+) -> Result<Vec<(Artifact, MojangArtifact, PathBuf)>, Box<dyn Error>> {
+  let (os, arch) = (&config.target_os, &config.target_arch);
   let artifact_str: String = library.name.get_descriptor();
   if !optional(&artifact_str) {
     info!("Considering library {artifact_str}: Not downloading {{Disabled}}");
-    return Ok(());
+    return Ok(vec![]);
+  }
+  if !library.is_allowed(os, arch) {
+    info!("Considering library {artifact_str}: Not downloading {{Wrong Os}}");
+    return Ok(vec![]);
   }
   info!("Considering library {artifact_str}");
+  let mut pending = vec![];
+  for (artifact, download) in library.get_artifacts(os, arch) {
+    if artifact != library.name {
+      info!("  Classifier: {artifact}");
+    }
+    if let Some((download, target)) = resolve_artifact(source, &artifact, download, root, grabbed, additional_library_dirs).await? {
+      pending.push((artifact, download, target));
+    }
+  }
+  Ok(pending)
+}
+
+/// Read-only counterpart of [`resolve_library`] and [`download_library_artifact`]: the actions they would
+/// take for `library`, without extracting, copying or downloading anything.
+pub async fn plan_library(
+  source: &mut ForgeInstallProfileSource<impl Read + Seek>,
+  library: &MojangLibrary,
+  root: &Path,
+  optional: &dyn Fn(&str) -> bool,
+  additional_library_dirs: &Vec<&PathBuf>,
+  config: &DownloadConfig
+) -> Result<Vec<PlannedAction>, Box<dyn Error>> {
+  let (os, arch) = (&config.target_os, &config.target_arch);
+  if !optional(&library.name.get_descriptor()) || !library.is_allowed(os, arch) {
+    return Ok(vec![]);
+  }
+  let mut actions = vec![];
+  for (artifact, download) in library.get_artifacts(os, arch) {
+    let target = artifact.get_local_path(root);
+    if target.is_file() {
+      match &download.sha1 {
+        Some(lib_sha1) if &Sha1Sum::from_file_async(&target).await? != lib_sha1 => {}
+        _ => continue,
+      }
+    }
+    let entry = format!("maven/{}", artifact.get_path_string());
+    if source.by_name(&entry).is_ok() {
+      actions.push(PlannedAction::Extract { entry, target });
+      continue;
+    }
+    let mut local = None;
+    if let Some(lib_sha1) = &download.sha1 {
+      for path in additional_library_dirs.iter().map(|lib_dir| artifact.get_local_path(lib_dir)) {
+        if Sha1Sum::from_file_async(&path).await.is_ok_and(|sum| &sum == lib_sha1) {
+          local = Some(path);
+          break;
+        }
+      }
+    }
+    if let Some(from) = local {
+      actions.push(PlannedAction::Copy { from, to: target });
+      continue;
+    }
+    if let Some(url) = download.url.filter(|url| !url.is_empty()) {
+      actions.push(PlannedAction::Download { url: Some(url), target });
+    }
+  }
+  Ok(actions)
+}
+
+async fn resolve_artifact(
+  source: &mut ForgeInstallProfileSource<impl Read + Seek>,
+  artifact: &Artifact,
+  download: MojangArtifact,
+  root: &Path,
+  grabbed: &mut HashSet<Artifact>,
+  additional_library_dirs: &Vec<&PathBuf>
+) -> Result<Option<(MojangArtifact, PathBuf)>, Box<dyn Error>> {
+  let target = artifact.get_local_path(root);
   if target.is_file() {
     if let Some(lib_sha1) = &download.sha1 {
-      let target_sha1 = Sha1Sum::from_reader(&mut File::open(&target)?)?;
+      let target_sha1 = Sha1Sum::from_file_async(&target).await?;
       if lib_sha1 == &target_sha1 {
         info!("  File exists: Checksum validated.");
-        return Ok(());
+        return Ok(None);
       }
       info!("  File exists: Checksum invalid, deleting file:");
       info!("    Expected: {lib_sha1}");
@@ -51,20 +352,19 @@ pub async fn download_library(
       }
     } else {
       info!("  File exists: No checksum, Assuming valid.");
-      return Ok(());
+      return Ok(None);
     }
   }
-  create_dir_all(&target.parent().unwrap())?;
-  if let Some(_) = try_to_extract_artifact(zip_archive, artifact, &download, grabbed, &target)? {
-    return Ok(());
+  create_dir_all(target.parent().unwrap())?;
+  if let Some(_) = try_to_extract_artifact(source, artifact, &download, grabbed, &target).await? {
+    return Ok(None);
   }
   if let Some(ref provided_sha1) = download.sha1 {
     for lib_dir in additional_library_dirs {
-      let in_lib_dir = artifact.get_local_path(&lib_dir);
+      let in_lib_dir = artifact.get_local_path(lib_dir);
       if in_lib_dir.is_file() {
         info!("  Found artifact in local folder {}", lib_dir.to_str().unwrap());
-        let sha1 = Sha1Sum::from_reader(&mut File::open(&in_lib_dir)?)?;
-        if provided_sha1 == &sha1 {
+        if &Sha1Sum::from_file_async(&in_lib_dir).await? == provided_sha1 {
           info!("    Checksum validated");
         } else {
           info!("    Invalid checksum. Not using.");
@@ -78,37 +378,33 @@ pub async fn download_library(
           }
         } else {
           info!("    Successfully copied local file");
-          grabbed.push(artifact.clone());
-          return Ok(());
+          grabbed.insert(artifact.clone());
+          return Ok(None);
         }
       }
     }
   }
-  let url = download.url.as_ref();
-  if url.is_none() || url.unwrap().is_empty() {
-    return Err(Box::new(io::Error::new(ErrorKind::Other, "Invalid library, missing url")));
-  }
-  if let Err(err) = download_lib(/* mirror */ &download, &target).await {
-    Err(Box::new(io::Error::new(ErrorKind::Other, format!("Failed to download library: {err}"))))
-  } else {
-    grabbed.push(artifact.clone());
-    Ok(())
+  // Provided libraries (like the forge jar the processors build) have an empty url, they aren't downloaded
+  if download.url.as_ref().is_none_or(String::is_empty) {
+    info!("  No url, expected to be provided: Not downloading");
+    return Ok(None);
   }
+  Ok(Some((download, target)))
 }
 
-fn try_to_extract_artifact(
-  zip_archive: &mut ZipArchive<impl Read + Seek>,
+async fn try_to_extract_artifact(
+  source: &mut ForgeInstallProfileSource<impl Read + Seek>,
   artifact: &Artifact,
   download: &MojangArtifact,
-  grabbed: &mut Vec<Artifact>,
-  target: &PathBuf
+  grabbed: &mut HashSet<Artifact>,
+  target: &Path
 ) -> Result<Option<()>, Box<dyn Error>> {
   let path = format!("maven/{}", artifact.get_path_string());
-  if let Ok(mut input) = zip_archive.by_name(&path) {
+  if let Ok(mut input) = source.by_name(&path) {
     info!("  Extracting library from /{path}");
-    io::copy(&mut input, &mut File::create(&target)?)?;
+    io::copy(&mut input, &mut File::create(target)?)?;
     if let Some(lib_sha1) = download.sha1.as_ref() {
-      let target_sha1 = Sha1Sum::from_reader(&mut File::open(&target)?)?;
+      let target_sha1 = Sha1Sum::from_file_async(target).await?;
       if lib_sha1 == &target_sha1 {
         info!("  File exists: Checksum validated.");
         return Ok(Some(()));
@@ -116,128 +412,423 @@ fn try_to_extract_artifact(
       info!("  File exists: Checksum invalid, deleting file:");
       info!("    Expected: {lib_sha1}");
       info!("    Found:    {target_sha1}");
-      if let Err(err) = fs::remove_file(&target) {
+      if let Err(err) = fs::remove_file(target) {
         error!("Failed to delete file, aborting. {}", err);
         return Err(Box::new(io::Error::new(ErrorKind::Other, format!("Failed to delete file, aborting. {}", err))));
       }
     }
     info!("  File exists: No checksum, Assuming valid.");
-    grabbed.push(artifact.clone());
+    grabbed.insert(artifact.clone());
     Ok(Some(()))
   } else {
     Ok(None)
   }
 }
 
-async fn download_lib(/* mirror */ download: &MojangArtifact, target: &PathBuf) -> Result<(), Box<dyn Error>> {
+/// Downloads a single artifact of a library to `target`, validating it against its own checksum
+pub async fn download_library_artifact(download: &MojangArtifact, target: &Path, config: &DownloadConfig) -> Result<(), Box<dyn Error>> {
   let url = download.url.as_ref().unwrap();
   info!("  Downloading library from {url}");
-  let bytes = Client::new().get(url).send().await?.bytes().await?;
-  fs::write(&target, bytes)?;
-  if let Some(sha1_lib) = &download.sha1 {
-    let sha1 = Sha1Sum::from_reader(&mut File::open(&target)?)?;
-    if sha1_lib == &sha1 {
-      info!("    Download completed: Checksum validated.");
-      return Ok(());
-    }
-    info!("    Download failed: Checksum invalid, deleting file:");
-    info!("      Expected: {sha1_lib}");
-    info!("      Actual:   {sha1}");
-    if fs::remove_file(&target).is_err() {
-      error!("Failed to delete file, aborting.");
-      return Err(Box::new(io::Error::new(ErrorKind::Other, "Failed to delete file, aborting.")));
-    }
+  let checksums = download.sha1.iter().cloned().map(Checksum::from).collect::<Vec<_>>();
+  download_file_with_mirrors(target, url, &checksums, download.size, config).await?;
+  if !checksums.is_empty() {
+    info!("    Download completed: Checksum validated.");
   }
   Ok(())
 }
 
 // V1
 
-pub fn extract_file<T: Read + Seek>(name: &str, target: &PathBuf, zip_archive: &mut ZipArchive<T>) -> Result<(), Box<dyn Error>> {
+pub fn extract_file<T: Read + Seek>(name: &str, target: &Path, source: &mut ForgeInstallProfileSource<T>) -> Result<(), Box<dyn Error>> {
   let path = if name.starts_with("/") { &name[1..] } else { name };
 
-  let input = zip_archive.by_name(&path);
+  let input = source.by_name(path);
   if let Err(err) = input {
-    match err {
-      ZipError::FileNotFound => { Err(forge_err!("File not found in installer archive: {}", path))? }
+    match err.kind() {
+      ErrorKind::NotFound => { Err(forge_err!("File not found in installer archive: {}", path))? }
       _ => Err(err)?,
     }
   } else {
     create_dir_all(target.parent().unwrap())?;
-    io::copy(&mut input?, &mut File::create(&target)?)?;
+    io::copy(&mut input?, &mut File::create(target)?)?;
     Ok(())
   }
 }
 
+/// Read-only counterpart of [`download_installed_libraries`]: the libraries it would download and
+/// extract from the installer
+pub async fn plan_installed_libraries(
+  is_client: bool,
+  libraries_dir: &Path,
+  libraries: &Vec<ForgeLibrary>,
+  archive: &mut ForgeInstallProfileSource<impl Read + Seek>,
+  config: &DownloadConfig
+) -> Result<Vec<PlannedAction>, Box<dyn Error>> {
+  let mut actions = vec![];
+  for library in libraries {
+    let artifact = &library.name;
+    if !library.is_side(if is_client { "clientreq" } else { "serverreq" }) || !library.enabled {
+      continue;
+    }
+    let lib_path = artifact.get_local_path(libraries_dir);
+    if lib_path.is_file() && !library.checksums.is_empty() && library.checksums.contains(&Sha1Sum::from_file_async(&lib_path).await?) {
+      continue;
+    }
+    let lib_url = match config.get_url_override(artifact) {
+      Some(url) => url.clone(),
+      None => join_url(&library.get_url_or(&config.repositories.libraries_base), &artifact.get_path_string()),
+    };
+    actions.push(PlannedAction::Download { url: Some(lib_url), target: lib_path.clone() });
+    if archive.by_name(&artifact.get_path_string()).is_ok() {
+      actions.push(PlannedAction::Extract { entry: artifact.get_path_string(), target: lib_path });
+    }
+  }
+  Ok(actions)
+}
+
+/// Downloads the `libraries` of a V1 profile that are required on this side, falling back to the copies
+/// packed in the installer. Copies in the additional library directories are used first. Offline, a
+/// library found in neither fails with [`ForgeInstallError::OfflineMissing`], unless it's an unmirrored
+/// Mojang library left for the launcher. Returns the libraries that couldn't be placed and the number of
+/// bytes downloaded.
 pub async fn download_installed_libraries(
   is_client: bool,
-  libraries_dir: &PathBuf,
+  libraries_dir: &Path,
   libraries: &Vec<ForgeLibrary>,
-  grabbed: &mut Vec<Artifact>,
-  bad: &mut Vec<Artifact>,
-  archive: &mut ZipArchive<impl Read + Seek>
-) -> Result<i32, Box<dyn Error>> {
+  grabbed: &mut HashSet<Artifact>,
+  archive: &mut ForgeInstallProfileSource<impl Read + Seek>,
+  config: &DownloadConfig,
+  cancel: &CancellationToken
+) -> Result<(Vec<Artifact>, u64), Box<dyn Error>> {
   let mut progress = 1;
+  let mut bad = vec![];
+  let mut downloaded = 0;
+  let report = |artifact: &Artifact, progress: usize, outcome: LibraryOutcome| {
+    if let Some(on_library) = &config.on_library {
+      (on_library.0)(artifact, progress, libraries.len(), outcome);
+    }
+  };
   for library in libraries {
+    cancel.check()?;
     let artifact = &library.name;
-    let checksums = &library.checksums;
+    let checksums = library.checksums.iter().cloned().map(Checksum::from).collect::<Vec<_>>();
     if library.is_side(if is_client { "clientreq" } else { "serverreq" }) && library.enabled {
       info!("Considering library {} ({}/{})", artifact.get_descriptor(), progress, libraries.len());
-      let lib_path = artifact.get_local_path(&libraries_dir);
-      let checksum = Sha1Sum::from_reader(&mut File::open(&lib_path)?)?;
-      if lib_path.exists() && !checksums.is_empty() && checksums.contains(&checksum) {
+      let lib_path = artifact.get_local_path(libraries_dir);
+      // Only hash files that are already there, on a first install the library is simply downloaded
+      if lib_path.is_file() && !library.checksums.is_empty() {
+        let checksum = Sha1Sum::from_file_async(&lib_path).await?;
+        if library.checksums.contains(&checksum) {
+          report(artifact, progress, LibraryOutcome::Valid);
+          progress += 1;
+          continue;
+        }
+      }
+      create_dir_all(lib_path.parent().unwrap())?;
+      if copy_from_library_dirs(artifact, &library.checksums, &config.additional_library_dirs, &lib_path).await {
+        grabbed.insert(artifact.clone());
+        report(artifact, progress, LibraryOutcome::Grabbed);
+        progress += 1;
+        continue;
+      }
+      // Keeps any path prefix of the base, so repositories can live under a sub path of a mirror
+      let lib_url = match config.get_url_override(artifact) {
+        Some(url) => url.clone(),
+        None => join_url(&library.get_url_or(&config.repositories.libraries_base), &artifact.get_path_string()),
+      };
+      let unmirrored = lib_url.starts_with(&config.repositories.libraries_base) && is_client;
+      if config.offline {
+        // Nothing is downloaded, the installer's copy is the last one left
+        if extract_file(&artifact.get_path_string(), &lib_path, archive).is_ok() {
+          grabbed.insert(artifact.clone());
+          report(artifact, progress, LibraryOutcome::Grabbed);
+        } else if unmirrored && !config.treat_unmirrored_mojang_failure_as_fatal {
+          warn!("  Unmirrored file not available offline, Mojang launcher should download at next run, non fatal");
+          report(artifact, progress, LibraryOutcome::Unavailable);
+        } else {
+          Err(ForgeInstallError::OfflineMissing(artifact.get_descriptor()))?;
+        }
         progress += 1;
         continue;
       }
-      create_dir_all(&lib_path.parent().unwrap())?;
       info!("  Downloading library {}", artifact.get_descriptor());
-      let mut lib_url = Url::parse(&library.get_url())?;
-      lib_url.set_path(&artifact.get_path_string());
-      let lib_url = lib_url.as_str().to_string();
       info!("  Trying unpacked library {}", artifact.get_descriptor());
 
-      let download_file_result = download_file(&lib_path, &lib_url, &checksums).await;
+      let download_file_result = download_file_with_mirrors(&lib_path, &lib_url, &checksums, None, config).await;
+      if download_file_result.is_ok() {
+        downloaded += fs::metadata(&lib_path).map(|metadata| metadata.len()).unwrap_or(0);
+      }
       let extract_file_result = extract_file(&artifact.get_path_string(), &lib_path, archive);
       if download_file_result.is_err() && extract_file_result.is_err() {
-        if !lib_url.starts_with("https://libraries.minecraft.net/") || !is_client {
+        if !unmirrored || config.treat_unmirrored_mojang_failure_as_fatal {
           debug!("Download file error: {}", download_file_result.unwrap_err());
           debug!("Extract file error: {}", extract_file_result.unwrap_err());
           bad.push(artifact.clone());
+          report(artifact, progress, LibraryOutcome::Bad);
         } else {
           warn!("  Unmirrored file failed, Mojang launcher should download at next run, non fatal");
+          report(artifact, progress, LibraryOutcome::Unavailable);
         }
       } else {
-        grabbed.push(artifact.clone());
+        grabbed.insert(artifact.clone());
+        report(artifact, progress, LibraryOutcome::Grabbed);
       }
     } else if library.is_side(if is_client { "clientreq" } else { "serverreq" }) {
       warn!("Considering library {}: Not Downloading {}", artifact.get_descriptor(), "{Disabled}");
+      report(artifact, progress, LibraryOutcome::Skipped);
     } else {
       warn!("Considering library {}: Not downloading {}", artifact.get_descriptor(), "{Wrong Side}");
+      report(artifact, progress, LibraryOutcome::Skipped);
     }
     progress += 1;
   }
 
-  Ok(progress)
+  Ok((bad, downloaded))
 }
 
-pub async fn download_file(lib_path: &PathBuf, lib_url: &str, checksums: &Vec<Sha1Sum>) -> Result<(), Box<dyn Error>> {
-  let response = Client::new().get(lib_url).send().await?;
-  if !response.status().is_success() {
-    Err(forge_err!("Failed to download file: {}. Status: {}", lib_url, response.status().as_u16()))?;
+/// Copies `artifact` into `target` from the first of `library_dirs` holding a copy that matches one of
+/// `checksums`, any copy is used when there are none. Returns whether it was copied.
+async fn copy_from_library_dirs(artifact: &Artifact, checksums: &[Sha1Sum], library_dirs: &[PathBuf], target: &Path) -> bool {
+  for lib_dir in library_dirs {
+    let in_lib_dir = artifact.get_local_path(lib_dir);
+    if !in_lib_dir.is_file() {
+      continue;
+    }
+    info!("  Found artifact in local folder {}", lib_dir.display());
+    if !checksums.is_empty() {
+      match Sha1Sum::from_file_async(&in_lib_dir).await {
+        Ok(sum) if checksums.contains(&sum) => info!("    Checksum validated"),
+        _ => {
+          info!("    Invalid checksum. Not using.");
+          continue;
+        }
+      }
+    }
+    match fs::copy(&in_lib_dir, target) {
+      Ok(_) => {
+        info!("    Successfully copied local file");
+        return true;
+      }
+      Err(err) => warn!("    Failed to copy from local folder: {err}"),
+    }
+  }
+  false
+}
+
+/// Hashes the already present `libraries` concurrently with [`Sha1Sum::from_file_async`] and returns the ones
+/// that are missing or don't match their `expected` checksum. Libraries without an expected checksum only
+/// have to exist.
+pub async fn verify_libraries(libraries: &[Artifact], libraries_dir: &Path, expected: &HashMap<Artifact, Sha1Sum>) -> Vec<Artifact> {
+  let checks = libraries.iter().map(|artifact| {
+    let path = artifact.get_local_path(libraries_dir);
+    let expected = expected.get(artifact).cloned();
+    let artifact = artifact.clone();
+    async move {
+      let valid = match expected {
+        Some(expected) => Sha1Sum::from_file_async(&path).await.is_ok_and(|sum| sum == expected),
+        None => path.is_file(),
+      };
+      (artifact, valid)
+    }
+  });
+  stream
+    ::iter(checks)
+    .buffer_unordered(VERIFY_CONCURRENCY)
+    .filter_map(|(artifact, valid)| async move { (!valid).then_some(artifact) })
+    .collect().await
+}
+
+/// Downloads `lib_url` into a `.part` file next to `lib_path`, which is only moved into place once the
+/// checksum validates. A `.part` file left over by an interrupted download is resumed with a range
+/// request, if the server doesn't support ranges the download restarts from zero. Responses with a gzip,
+/// brotli or deflate `Content-Encoding` are decoded by reqwest, so the checksum is computed over the
+/// decoded bytes, the same ones written to disk. `size` is the declared size of the file, reported to
+/// [`DownloadConfig::on_progress`] instead of the `Content-Length`.
+pub async fn download_file(
+  lib_path: &Path,
+  lib_url: &str,
+  checksums: &Vec<Checksum>,
+  size: Option<u64>,
+  config: &DownloadConfig
+) -> Result<(), Box<dyn Error>> {
+  download_file_with_progress(lib_path, lib_url, checksums, config, |transferred, total| {
+    if let Some(on_progress) = &config.on_progress {
+      (on_progress.0)(lib_url, transferred, size.or(total));
+    }
+  }).await
+}
+
+/// Same as [`download_file`], calling `on_progress` with the bytes written so far and the total size
+/// (when the server sends a `Content-Length`) after every chunk.
+pub async fn download_file_with_progress(
+  lib_path: &Path,
+  lib_url: &str,
+  checksums: &Vec<Checksum>,
+  config: &DownloadConfig,
+  mut on_progress: impl FnMut(u64, Option<u64>)
+) -> Result<(), Box<dyn Error>> {
+  if config.offline {
+    Err(ForgeInstallError::OfflineMissing(lib_url.to_string()))?;
   }
-  let mut stream = response.bytes_stream();
   create_dir_all(lib_path.parent().unwrap())?;
+  let _permit = match config.max_concurrent_per_host {
+    Some(limit) => config.host_limiter.acquire(lib_url, limit).await,
+    None => None,
+  };
+  let client = config.build_client()?;
+  let part_path = get_part_path(lib_path);
+  let mut resume_from = part_path.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+  let response = loop {
+    let mut request = client.get(lib_url).headers(config.repositories.get_headers(lib_url));
+    if resume_from > 0 {
+      // The range has to refer to the bytes on disk, not to a compressed encoding of them
+      request = request.header(RANGE, format!("bytes={resume_from}-")).header(ACCEPT_ENCODING, "identity");
+    }
+    let response = match tokio::time::timeout(config.request_timeout, request.send()).await {
+      Ok(response) => response?,
+      Err(_) => Err(io::Error::new(ErrorKind::TimedOut, format!("No response from {lib_url} after {}s", config.request_timeout.as_secs())))?,
+    };
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE && resume_from > 0 {
+      fs::remove_file(&part_path)?;
+      resume_from = 0;
+      continue;
+    }
+    break response;
+  };
+  if !response.status().is_success() {
+    Err(ForgeInstallError::Status(lib_url.to_string(), response.status().as_u16()))?;
+  }
+  let resuming = resume_from > 0 && response.status() == StatusCode::PARTIAL_CONTENT;
+  let mut transferred = if resuming { resume_from } else { 0 };
+  let total = response.content_length().map(|length| length + transferred);
 
-  let mut sha1_hasher = Sha1::new();
-  let mut writer = File::create(&lib_path)?;
-  while let Some(item) = stream.next().await {
-    let chunk = item?;
-    sha1_hasher.update(&chunk);
+  // Only the strongest algorithm the caller knows about is checked
+  let mut hasher = Checksum::get_strongest_algorithm(checksums).map(ChecksumHasher::new);
+  let mut writer = if resuming {
+    info!("    Resuming download from byte {resume_from}");
+    if let Some(hasher) = hasher.as_mut() {
+      hasher.update_from_reader(&mut File::open(&part_path)?)?;
+    }
+    OpenOptions::new().append(true).open(&part_path)?
+  } else {
+    File::create(&part_path)?
+  };
+  let mut stream = response.bytes_stream();
+  loop {
+    let chunk = match tokio::time::timeout(config.read_timeout, stream.next()).await {
+      Ok(Some(item)) => item?,
+      Ok(None) => break,
+      Err(_) => Err(io::Error::new(ErrorKind::TimedOut, format!("Download of {lib_url} stalled for {}s", config.read_timeout.as_secs())))?,
+    };
+    if let Some(hasher) = hasher.as_mut() {
+      hasher.update(&chunk);
+    }
     writer.write_all(&chunk)?;
+    transferred += chunk.len() as u64;
+    on_progress(transferred, total);
   }
-  let sum = Sha1Sum::new(sha1_hasher.finalize().into());
-  if !checksums.is_empty() && !checksums.contains(&sum) {
-    Err(forge_err!("Checksum failed: Actual: {sum} Expected: {checksums:?}"))?;
+  writer.flush()?;
+  drop(writer);
+  if let Some(hasher) = hasher {
+    let sum = hasher.finalize();
+    if !checksums.contains(&sum) {
+      info!("    Download failed: Checksum invalid, deleting file");
+      if fs::remove_file(&part_path).is_err() {
+        error!("Failed to delete file, aborting.");
+        return Err(Box::new(io::Error::new(ErrorKind::Other, "Failed to delete file, aborting.")));
+      }
+      Err(ForgeInstallError::Checksum(format!("Actual: {sum} Expected: {checksums:?}")))?;
+    }
   }
+  fs::rename(&part_path, lib_path)?;
   Ok(())
 }
+
+fn get_part_path(path: &Path) -> PathBuf {
+  let mut part_path = path.as_os_str().to_owned();
+  part_path.push(".part");
+  PathBuf::from(part_path)
+}
+
+/// Same as [`download_file`], but retries up to `config.retries` times with exponential backoff starting
+/// at `config.retry_base_delay`. Connection errors, timeouts, 5xx/429 statuses and checksum mismatches
+/// are retried, any other status (e.g. 404) fails right away.
+pub async fn download_file_with_retries(
+  lib_path: &Path,
+  lib_url: &str,
+  checksums: &Vec<Checksum>,
+  size: Option<u64>,
+  config: &DownloadConfig
+) -> Result<(), Box<dyn Error>> {
+  let (retries, base_delay) = (config.retries, config.retry_base_delay);
+  let mut attempt = 0;
+  loop {
+    let result = download_file(lib_path, lib_url, checksums, size, config).await;
+    let err = match result {
+      Ok(()) => return Ok(()),
+      Err(err) => err,
+    };
+    if attempt >= retries || !is_retryable(err.as_ref()) {
+      return Err(err);
+    }
+    let delay = base_delay.saturating_mul(2u32.saturating_pow(attempt));
+    attempt += 1;
+    warn!("    Download failed ({err}), retrying in {}ms ({attempt}/{retries})", delay.as_millis());
+    tokio::time::sleep(delay).await;
+  }
+}
+
+/// Tries every mirror in `config.mirrors` before falling back to `lib_url`. The checksum is validated
+/// after each attempt, so a mirror serving a corrupt file is skipped instead of kept.
+pub async fn download_file_with_mirrors(
+  lib_path: &Path,
+  lib_url: &str,
+  checksums: &Vec<Checksum>,
+  size: Option<u64>,
+  config: &DownloadConfig
+) -> Result<(), Box<dyn Error>> {
+  for mirror in config.mirrors.iter().filter(|_| !config.offline) {
+    let url = match mirror_url(lib_url, mirror) {
+      Some(url) => url,
+      None => {
+        warn!("    Invalid mirror url: {mirror}");
+        continue;
+      }
+    };
+    info!("  Trying mirror {url}");
+    match download_file_with_retries(lib_path, &url, checksums, size, config).await {
+      Ok(()) => return Ok(()),
+      Err(err) => warn!("    Mirror {mirror} failed: {err}"),
+    }
+  }
+  download_file_with_retries(lib_path, lib_url, checksums, size, config).await
+}
+
+pub fn mirror_url(url: &str, mirror: &str) -> Option<String> {
+  let url = Url::parse(url).ok()?;
+  let mut mirror = Url::parse(mirror).ok()?;
+  let prefix = mirror.path().trim_end_matches('/').to_string();
+  mirror.set_path(&format!("{prefix}{}", url.path()));
+  mirror.set_query(url.query());
+  Some(mirror.to_string())
+}
+
+pub(crate) fn is_retryable(err: &(dyn Error + 'static)) -> bool {
+  if let Some(err) = err.downcast_ref::<ForgeInstallError>() {
+    return match err {
+      ForgeInstallError::Status(_, status) => *status == 429 || (500..600).contains(status),
+      ForgeInstallError::Checksum(_) => true,
+      _ => false,
+    };
+  }
+  if let Some(err) = err.downcast_ref::<io::Error>() {
+    return matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::UnexpectedEof);
+  }
+  // Builder, decode and redirect errors fail the same way every time
+  match err.downcast_ref::<reqwest::Error>() {
+    Some(err) => {
+      err.is_timeout() || err.is_connect() || err.is_body() || err.status().is_some_and(|status| status.as_u16() == 429 || status.is_server_error())
+    }
+    None => false,
+  }
+}
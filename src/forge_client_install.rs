@@ -1,29 +1,59 @@
 use std::{
   borrow::BorrowMut,
+  collections::{ HashMap, HashSet },
   error::Error,
-  fs::{ self, create_dir_all, File },
-  io::{ self, ErrorKind, Write },
+  fs::{ self, create_dir_all, File, OpenOptions },
+  io::{ self, Cursor, ErrorKind, Read, Seek, Write },
   ops::Deref,
-  path::PathBuf,
-  sync::Arc,
+  path::{ Path, PathBuf },
+  process,
+  sync::{ atomic::{ AtomicBool, Ordering }, Arc },
+  time::{ Duration, Instant },
 };
 
-use log::{info, debug, error};
+use futures::{ stream, StreamExt };
 use reqwest::Client;
+use log::{info, debug, error, warn};
 use thiserror::Error;
-use zip::{ write::FileOptions, ZipArchive, ZipWriter };
+use zip::{ ZipArchive, ZipWriter };
 
 use crate::{
-  get_vanilla_version,
+  get_vanilla_version_with_cache,
+  parse_vanilla_version,
+  join_url,
   Artifact,
-  download_utils::{ self, download_library },
-  forge_installer_profile::{ v1::{ ForgeLibrary, ForgeOptional }, ForgeInstallerProfile, ForgeVersionInfo, ForgeVersionLibrary },
+  Checksum,
+  Sha1Sum,
+  download_utils::{ self, resolve_library, download_library_artifact, DownloadConfig, ProgressCallback },
+  java::{ verify_java, JavaVersion },
+  forge_installer_profile::{
+    v1::{ ForgeLibrary, ForgeOptional },
+    v2::{ JvmOptions, MojangLibrary, ProcessorHook, TargetArch, TargetOs },
+    source::ForgeInstallProfileSource,
+    ForgeInstallerProfile,
+    ForgeVersionInfo,
+    ForgeVersionLibrary,
+  },
   post_processors::PostProcessors,
 };
 
 #[derive(Debug, Error)]
 pub enum ForgeInstallError {
   #[error("ForgeInstallError: {0}")] Other(String),
+  #[error("Failed to download file: {0}. Status: {1}")] Status(String, u16),
+  #[error("Checksum failed: {0}")] Checksum(String),
+  #[error("Install cancelled")] Cancelled,
+  #[error("Another install holds {0}, delete it if no install is running")] Locked(String),
+  #[error("Offline install, not available locally: {0}")] OfflineMissing(String),
+  #[error("install_profile.json not found in installer: {0}")] ProfileNotFound(String),
+  #[error("Couldn't parse installer profile: {0}")] InvalidProfile(String),
+  #[error("No installer found at {0}, check the minecraft and loader versions")] InstallerNotFound(String),
+  #[error("Processor {0} was rejected before running")] ProcessorRejected(String),
+  #[error("Processor {jar} failed with exit code {code:?}:\n{stderr}")] ProcessorFailed {
+    jar: String,
+    code: Option<i32>,
+    stderr: String,
+  },
 }
 
 #[macro_export]
@@ -33,22 +63,393 @@ macro_rules! forge_err {
   };
 }
 
-pub struct ForgeClientInstall {
-  installer_path: PathBuf,
+/// Cheap to clone handle used to abort an install from another task
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::SeqCst);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::SeqCst)
+  }
+
+  pub fn check(&self) -> Result<(), ForgeInstallError> {
+    if self.is_cancelled() { Err(ForgeInstallError::Cancelled) } else { Ok(()) }
+  }
+}
+
+/// Marks a game directory as being installed into until dropped. The lock file is created exclusively, so a
+/// second install into the same directory, from this process or another one, fails with
+/// [`ForgeInstallError::Locked`] instead of racing on `libraries/` and `versions/`. An install that was killed
+/// leaves the file behind, and it has to be deleted by hand.
+#[derive(Debug)]
+pub struct InstallLock {
+  path: PathBuf,
+}
+
+impl InstallLock {
+  pub const FILE_NAME: &'static str = ".forge-install.lock";
+
+  pub fn acquire(mc_dir: &Path) -> Result<Self, ForgeInstallError> {
+    let path = mc_dir.join(Self::FILE_NAME);
+    match OpenOptions::new().write(true).create_new(true).open(&path) {
+      Ok(mut file) => {
+        // Only informative, to find the owner of a stale lock
+        let _ = write!(file, "{}", process::id());
+        Ok(Self { path })
+      }
+      Err(err) if err.kind() == ErrorKind::AlreadyExists => Err(ForgeInstallError::Locked(path.display().to_string())),
+      Err(err) => Err(forge_err!("Failed to create install lock {}: {err}", path.display())),
+    }
+  }
+}
+
+impl Drop for InstallLock {
+  fn drop(&mut self) {
+    if let Err(err) = fs::remove_file(&self.path) {
+      warn!("Failed to remove install lock {}: {err}", self.path.display());
+    }
+  }
+}
+
+/// Everything an install would fetch, computed from the profile alone
+#[derive(Debug, Clone)]
+pub struct InstallPlan {
+  /// Libraries and their size in bytes, when the profile knows it
+  pub libraries: Vec<(Artifact, Option<u64>)>,
+  pub processor_jars: Vec<Artifact>,
+  pub total_known_bytes: u64,
+}
+
+/// Summary of a finished [`ForgeClientInstall::install_forge`]
+#[derive(Debug, Clone)]
+pub struct InstallReport {
+  pub version_id: String,
+  pub minecraft_version: String,
+  /// Libraries placed by this install, whether downloaded, copied or extracted
+  pub grabbed: Vec<Artifact>,
+  /// The grabbed libraries the installer jar ships a copy of
+  pub extracted_from_installer: Vec<Artifact>,
+  pub processors_run: usize,
+  /// Files produced by the processors (patched jars, mappings...) with their validated checksum, including
+  /// the ones already up to date
+  pub processor_outputs: HashMap<PathBuf, Sha1Sum>,
+  pub bytes_downloaded: u64,
+  pub duration: Duration,
+  /// What a dry run would have done, empty for a real install
+  pub planned_actions: Vec<PlannedAction>,
+}
+
+/// Where a library of an install comes from, see [`ForgeClientInstall::classify_libraries`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum LibrarySource {
+  /// Packed in the installer
+  Bundled,
+  /// Downloaded from this url
+  Remote(String),
+  /// Neither packed nor with a url
+  Unknown,
+}
+
+/// A single step of an install, as recorded by a dry run
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlannedAction {
+  CreateDir(PathBuf),
+  WriteFile(PathBuf),
+  /// The url is `None` when it's only known from a version manifest that isn't cached yet
+  Download { url: Option<String>, target: PathBuf },
+  /// Extracts an entry of the installer archive
+  Extract { entry: String, target: PathBuf },
+  Copy { from: PathBuf, to: PathBuf },
+  /// Runs a processor, `command` starts with the java executable
+  RunProcessor { jar: Artifact, command: Vec<String> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstallStatus {
+  /// The version json is missing or can't be parsed
+  Missing,
+  /// The version json is there, but these libraries are missing or fail their checksum
+  Corrupt(Vec<Artifact>),
+  Complete,
+}
+
+/// `R` is the installer archive's reader, a [`File`] for [`Self::new`] or a [`Cursor`] for [`Self::from_bytes`].
+/// An installer extracted to a directory has no archive, it uses [`File`].
+pub struct ForgeClientInstall<R: Read + Seek = File> {
+  /// None when the installer was only loaded in memory
+  installer_path: Option<PathBuf>,
 
   profile: Arc<ForgeInstallerProfile>,
   processors: Option<PostProcessors>,
   version: ForgeVersionInfo,
-  archive: ZipArchive<File>,
-  grabbed: Vec<Artifact>,
+  source: ForgeInstallProfileSource<R>,
+  grabbed: HashSet<Artifact>,
+  download_config: DownloadConfig,
+  /// None when the installer has no processors and java wasn't needed
+  java_version: Option<JavaVersion>,
+  cancellation_token: CancellationToken,
+  created_files: Vec<PathBuf>,
+  keep_inherits_from: bool,
+  dry_run: bool,
+  skip_vanilla: bool,
+  minecraft_version: Option<String>,
+}
+
+/// Collects the install options, so [`ForgeClientInstall`] doesn't need a constructor per combination of them
+#[derive(Debug, Clone)]
+pub struct ForgeClientInstallBuilder {
+  java_path: PathBuf,
+  download_config: DownloadConfig,
+  temp_dir: Option<PathBuf>,
+  keep_temp_dir: bool,
+  jvm_options: JvmOptions,
+  on_before_processor: Option<ProcessorHook>,
+  keep_inherits_from: bool,
+  dry_run: bool,
+  skip_vanilla: bool,
+  minecraft_version: Option<String>,
 }
 
-impl ForgeClientInstall {
+impl Default for ForgeClientInstallBuilder {
+  fn default() -> Self {
+    Self {
+      java_path: PathBuf::from("java"),
+      download_config: DownloadConfig::default(),
+      temp_dir: None,
+      keep_temp_dir: false,
+      jvm_options: JvmOptions::default(),
+      on_before_processor: None,
+      keep_inherits_from: true,
+      dry_run: false,
+      skip_vanilla: false,
+      minecraft_version: None,
+    }
+  }
+}
+
+impl ForgeClientInstallBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Java used to run the processors, defaults to `java` from the PATH
+  pub fn java_path(mut self, java_path: PathBuf) -> Self {
+    self.java_path = java_path;
+    self
+  }
+
+  pub fn client(mut self, client: Client) -> Self {
+    self.download_config.client = Some(client);
+    self
+  }
+
+  pub fn mirrors(mut self, mirrors: Vec<String>) -> Self {
+    self.download_config.mirrors = mirrors;
+    self
+  }
+
+  pub fn max_concurrent_downloads(mut self, max_concurrent_downloads: usize) -> Self {
+    self.download_config.max_concurrent_downloads = max_concurrent_downloads;
+    self
+  }
+
+  pub fn max_concurrent_per_host(mut self, max_concurrent_per_host: usize) -> Self {
+    self.download_config.max_concurrent_per_host = Some(max_concurrent_per_host);
+    self
+  }
+
+  /// Platform to install for, defaults to the host
+  pub fn target_os(mut self, target_os: TargetOs) -> Self {
+    self.download_config.target_os = target_os;
+    self
+  }
+
+  /// Architecture to install for, defaults to the host
+  pub fn target_arch(mut self, target_arch: TargetArch) -> Self {
+    self.download_config.target_arch = target_arch;
+    self
+  }
+
+  /// Never touches the network: libraries are only extracted from the installer or copied from the
+  /// additional library directories, and the vanilla jar and json must already be installed
+  pub fn offline(mut self, offline: bool) -> Self {
+    self.download_config.offline = offline;
+    self
+  }
+
+  /// Downloads the library `descriptor` (e.g. `org.ow2.asm:asm:9.5`) from `url`, see [`DownloadConfig::url_overrides`]
+  pub fn url_override(mut self, descriptor: impl Into<String>, url: impl Into<String>) -> Self {
+    self.download_config.url_overrides.insert(descriptor.into(), url.into());
+    self
+  }
+
+  /// See [`DownloadConfig::vanilla_version_json`]
+  pub fn vanilla_version_json(mut self, vanilla_version_json: serde_json::Value) -> Self {
+    self.download_config.vanilla_version_json = Some(vanilla_version_json);
+    self
+  }
+
+  /// See [`DownloadConfig::treat_unmirrored_mojang_failure_as_fatal`]
+  pub fn treat_unmirrored_mojang_failure_as_fatal(mut self, fatal: bool) -> Self {
+    self.download_config.treat_unmirrored_mojang_failure_as_fatal = fatal;
+    self
+  }
+
+  pub fn retries(mut self, retries: u32) -> Self {
+    self.download_config.retries = retries;
+    self
+  }
+
+  pub fn additional_library_dirs(mut self, additional_library_dirs: Vec<PathBuf>) -> Self {
+    self.download_config.additional_library_dirs = additional_library_dirs;
+    self
+  }
+
+  pub fn temp_dir(mut self, temp_dir: PathBuf) -> Self {
+    self.temp_dir = Some(temp_dir);
+    self
+  }
+
+  /// Keeps the processors' extracted data files after the install, for debugging
+  pub fn keep_temp_dir(mut self, keep_temp_dir: bool) -> Self {
+    self.keep_temp_dir = keep_temp_dir;
+    self
+  }
+
+  /// Extra arguments for the java running the processors, passed before the classpath (e.g. `-Xmx2g`)
+  pub fn jvm_args(mut self, jvm_args: Vec<String>) -> Self {
+    self.jvm_options.args = jvm_args;
+    self
+  }
+
+  /// `JAVA_TOOL_OPTIONS` environment variable of the processors
+  pub fn java_tool_options(mut self, java_tool_options: String) -> Self {
+    self.jvm_options.java_tool_options = Some(java_tool_options);
+    self
+  }
+
+  /// Called with the command line of every processor before java runs it, to run, skip or abort it
+  pub fn on_before_processor(mut self, on_before_processor: ProcessorHook) -> Self {
+    self.on_before_processor = Some(on_before_processor);
+    self
+  }
+
+  pub fn keep_inherits_from(mut self, keep_inherits_from: bool) -> Self {
+    self.keep_inherits_from = keep_inherits_from;
+    self
+  }
+
+  pub fn dry_run(mut self, dry_run: bool) -> Self {
+    self.dry_run = dry_run;
+    self
+  }
+
+  pub fn skip_vanilla(mut self, skip_vanilla: bool) -> Self {
+    self.skip_vanilla = skip_vanilla;
+    self
+  }
+
+  /// See [`ForgeClientInstall::set_minecraft_version`]
+  pub fn minecraft_version(mut self, minecraft_version: impl Into<String>) -> Self {
+    self.minecraft_version = Some(minecraft_version.into());
+    self
+  }
+
+  /// Replaces every download option at once, including the ones set by the other methods
+  pub fn download_config(mut self, download_config: DownloadConfig) -> Self {
+    self.download_config = download_config;
+    self
+  }
+
+  pub fn build(self, installer_path: PathBuf) -> Result<ForgeClientInstall<File>, Box<dyn Error>> {
+    let source = ForgeInstallProfileSource::Zip(ZipArchive::new(File::open(&installer_path)?)?);
+    let install = ForgeClientInstall::from_source(source, Some(installer_path), &self)?;
+    Ok(self.apply(install))
+  }
+
+  /// Loads an installer that was downloaded into memory. Processors that reference the `{INSTALLER}`
+  /// path can't run without a file on disk and will fail.
+  pub fn build_from_bytes(self, bytes: Vec<u8>) -> Result<ForgeClientInstall<Cursor<Vec<u8>>>, Box<dyn Error>> {
+    let source = ForgeInstallProfileSource::Zip(ZipArchive::new(Cursor::new(bytes))?);
+    let install = ForgeClientInstall::from_source(source, None, &self)?;
+    Ok(self.apply(install))
+  }
+
+  /// Loads an installer extracted to `dir`, with `install_profile.json`, the version json and the `maven/`
+  /// and `data/` folders at its root. Like [`Self::build_from_bytes`], there's no `{INSTALLER}` path.
+  pub fn build_from_dir(self, dir: PathBuf) -> Result<ForgeClientInstall<File>, Box<dyn Error>> {
+    let install = ForgeClientInstall::from_source(ForgeInstallProfileSource::Directory(dir), None, &self)?;
+    Ok(self.apply(install))
+  }
+
+  fn verify_java(&self) -> Result<JavaVersion, ForgeInstallError> {
+    let java_version = verify_java(&self.java_path)?;
+    info!("Using java {} ({})", java_version.version, self.java_path.display());
+    Ok(java_version)
+  }
+
+  fn apply<R: Read + Seek>(self, mut install: ForgeClientInstall<R>) -> ForgeClientInstall<R> {
+    install.set_download_config(self.download_config);
+    install.set_keep_inherits_from(self.keep_inherits_from);
+    install.set_dry_run(self.dry_run);
+    install.set_skip_vanilla(self.skip_vanilla);
+    if self.minecraft_version.is_some() {
+      install.set_minecraft_version(self.minecraft_version);
+    }
+    if let Some(temp_dir) = self.temp_dir {
+      install.set_temp_dir(temp_dir);
+    }
+    install.set_keep_temp_dir(self.keep_temp_dir);
+    install.set_jvm_options(self.jvm_options);
+    install.set_on_before_processor(self.on_before_processor);
+    install
+  }
+}
+
+impl ForgeClientInstall<File> {
+  pub fn builder() -> ForgeClientInstallBuilder {
+    ForgeClientInstallBuilder::new()
+  }
+
   pub fn new(installer_path: PathBuf, java_path: PathBuf) -> Result<Self, Box<dyn Error>> {
-    let installer_reader = File::open(&installer_path)?;
-    let mut archive = ZipArchive::new(installer_reader)?;
-    let profile = ForgeInstallerProfile::from_reader(archive.by_name("install_profile.json")?);
-    let version = profile.get_version_json(&mut archive)?;
+    ForgeClientInstallBuilder::new().java_path(java_path).build(installer_path)
+  }
+}
+
+impl ForgeClientInstall<Cursor<Vec<u8>>> {
+  /// See [`ForgeClientInstallBuilder::build_from_bytes`]
+  pub fn from_bytes(bytes: Vec<u8>, java_path: PathBuf) -> Result<Self, Box<dyn Error>> {
+    ForgeClientInstallBuilder::new().java_path(java_path).build_from_bytes(bytes)
+  }
+}
+
+impl<R: Read + Seek> ForgeClientInstall<R> {
+  fn from_source(
+    mut source: ForgeInstallProfileSource<R>,
+    installer_path: Option<PathBuf>,
+    builder: &ForgeClientInstallBuilder
+  ) -> Result<Self, Box<dyn Error>> {
+    let profile = match (source.read_profile(), &installer_path) {
+      (Err(ForgeInstallError::ProfileNotFound(_)), Some(path)) => Err(ForgeInstallError::ProfileNotFound(path.display().to_string()))?,
+      (profile, _) => profile?,
+    };
+    let version = profile.get_version_json(&mut source)?;
+    // Java only runs the processors, V1 profiles and V2 profiles without any for the client install without it
+    let java_version = match &profile {
+      ForgeInstallerProfile::V2(v2) if !v2.get_processors("client").is_empty() => Some(builder.verify_java()?),
+      _ => {
+        info!("No client processors to run, skipping the java check");
+        None
+      }
+    };
 
     let profile = Arc::new(profile);
     let mut client_install = Self {
@@ -56,12 +457,20 @@ impl ForgeClientInstall {
       profile,
       processors: None,
       version,
-      archive,
-      grabbed: vec![],
+      source,
+      grabbed: HashSet::new(),
+      download_config: DownloadConfig::default(),
+      java_version,
+      cancellation_token: CancellationToken::new(),
+      created_files: vec![],
+      keep_inherits_from: true,
+      dry_run: false,
+      skip_vanilla: false,
+      minecraft_version: None,
     };
-    let new_profile = Arc::clone(&client_install.profile);
-    if let ForgeInstallerProfile::V2(_) = new_profile.deref() {
-      client_install.processors = PostProcessors::new(new_profile, true, java_path.clone()).ok();
+    if client_install.java_version.is_some() {
+      let new_profile = Arc::clone(&client_install.profile);
+      client_install.processors = Some(PostProcessors::new(new_profile, true, builder.java_path.clone())?);
     }
     Ok(client_install)
   }
@@ -70,8 +479,510 @@ impl ForgeClientInstall {
     Arc::clone(&self.profile)
   }
 
-  pub async fn install_forge(&mut self, mc_dir: &PathBuf, /* installer */ optionals: fn(&str) -> bool) -> Result<(), Box<dyn Error>> {
-    create_dir_all(&mc_dir)?;
+  pub fn get_installer_profile(&self) -> &ForgeInstallerProfile {
+    &self.profile
+  }
+
+  /// The version json embedded in the installer, with the main class, arguments and libraries needed to launch
+  pub fn get_version_info(&self) -> &ForgeVersionInfo {
+    &self.version
+  }
+
+  /// Version of the java running the processors, None when the installer has no processors to run
+  pub fn get_java_version(&self) -> Option<&JavaVersion> {
+    self.java_version.as_ref()
+  }
+
+  /// Token that aborts [`Self::install_forge`] when cancelled. Checked between libraries and processors,
+  /// a running processor has its java process killed.
+  pub fn get_cancellation_token(&self) -> CancellationToken {
+    self.cancellation_token.clone()
+  }
+
+  pub fn get_download_config(&self) -> &DownloadConfig {
+    &self.download_config
+  }
+
+  pub fn set_download_config(&mut self, download_config: DownloadConfig) {
+    if let Some(processors) = self.processors.as_mut() {
+      processors.set_target_os(download_config.target_os.clone());
+      processors.set_target_arch(download_config.target_arch.clone());
+    }
+    self.download_config = download_config;
+  }
+
+  pub fn get_target_os(&self) -> &TargetOs {
+    &self.download_config.target_os
+  }
+
+  /// Platform the install is for: picks the natives and the library rules, and how the processors' classpath
+  /// and paths are written. Defaults to the host, set it to plan an install for another platform.
+  pub fn set_target_os(&mut self, target_os: TargetOs) {
+    if let Some(processors) = self.processors.as_mut() {
+      processors.set_target_os(target_os.clone());
+    }
+    self.download_config.target_os = target_os;
+  }
+
+  pub fn get_target_arch(&self) -> &TargetArch {
+    &self.download_config.target_arch
+  }
+
+  /// Architecture the install is for, it picks the natives and the library rules with the target os
+  pub fn set_target_arch(&mut self, target_arch: TargetArch) {
+    if let Some(processors) = self.processors.as_mut() {
+      processors.set_target_arch(target_arch.clone());
+    }
+    self.download_config.target_arch = target_arch;
+  }
+
+  /// Hashes the installer file and compares it to `expected`, e.g. the sha1 published next to the
+  /// installer. Installers loaded from bytes or a directory have no file to hash and fail.
+  pub fn verify_installer_sha1(&self, expected: &Sha1Sum) -> Result<(), ForgeInstallError> {
+    let Some(installer_path) = &self.installer_path else {
+      return Err(forge_err!("No installer file to verify."));
+    };
+    let actual = File::open(installer_path)
+      .map_err(|err| forge_err!("Failed to open installer {}: {err}", installer_path.display()))
+      .and_then(|mut file| Sha1Sum::from_reader(&mut file).map_err(|err| forge_err!("Failed to hash installer: {err}")))?;
+    if &actual != expected {
+      return Err(ForgeInstallError::Checksum(format!("installer {}, expected {expected} but got {actual}", installer_path.display())));
+    }
+    Ok(())
+  }
+
+  /// Whether each library of the install (natives for the target os included) is packed in the installer
+  /// or has to be downloaded, without touching the disk or the network. Libraries already installed are
+  /// classified the same, so this is the worst case for a fresh game directory.
+  pub fn classify_libraries(&mut self) -> Vec<(Artifact, LibrarySource)> {
+    let mut classified = vec![];
+    match self.profile.deref() {
+      ForgeInstallerProfile::V1(profile) => {
+        for library in profile.get_libraries("clientreq", |_| true) {
+          let path = library.name.get_path_string();
+          let source = match self.source.by_name(&path) {
+            Ok(_) => LibrarySource::Bundled,
+            Err(_) =>
+              match self.download_config.get_url_override(&library.name) {
+                Some(url) => LibrarySource::Remote(url.clone()),
+                None => LibrarySource::Remote(join_url(&library.get_url_or(&self.download_config.repositories.libraries_base), &path)),
+              }
+          };
+          classified.push((library.name, source));
+        }
+      }
+      ForgeInstallerProfile::V2(_) => {
+        let mut libraries = self.version.libraries.iter().collect::<Vec<_>>();
+        libraries.extend(self.processors.as_ref().map(PostProcessors::get_libraries).unwrap_or_default());
+        let (os, arch) = (&self.download_config.target_os, &self.download_config.target_arch);
+        for library in libraries.into_iter().filter_map(ForgeVersionLibrary::to_mojang).filter(|library| library.is_allowed(os, arch)) {
+          for (artifact, download) in library.get_artifacts(os, arch) {
+            if classified.iter().any(|(classified, _)| classified == &artifact) {
+              continue;
+            }
+            let url = self.download_config.get_url_override(&artifact).cloned().or(download.url);
+            let source = match (self.source.by_name(&format!("maven/{}", artifact.get_path_string())), url) {
+              (Ok(_), _) => LibrarySource::Bundled,
+              (Err(_), Some(url)) if !url.is_empty() => LibrarySource::Remote(url),
+              (Err(_), _) => LibrarySource::Unknown,
+            };
+            classified.push((artifact, source));
+          }
+        }
+      }
+    }
+    classified
+  }
+
+  /// Copies every library packed under `maven/` in the installer into `target_dir`, keeping its repository
+  /// layout, e.g. to seed a local mirror. Returns the artifacts copied, files that aren't artifacts are
+  /// skipped.
+  pub fn extract_bundled_maven(&mut self, target_dir: &Path) -> Result<Vec<Artifact>, ForgeInstallError> {
+    let names = self.source.get_file_names().map_err(|err| forge_err!("Failed to list the installer files: {err}"))?;
+    let mut artifacts = vec![];
+    for name in names {
+      let Some(artifact) = name.strip_prefix("maven/").and_then(Artifact::from_path) else {
+        continue;
+      };
+      download_utils::extract_file(&name, &artifact.get_local_path(target_dir), &mut self.source)
+        .map_err(|err| forge_err!("Failed to extract {name}: {err}"))?;
+      artifacts.push(artifact);
+    }
+    Ok(artifacts)
+  }
+
+  /// Declared checksums of the processor jars and their classpath, to check them with
+  /// [`download_utils::verify_libraries`] before java runs them. Empty for installers without processors.
+  pub fn get_processor_checksums(&self) -> HashMap<Artifact, Sha1Sum> {
+    self.processors.as_ref().map(PostProcessors::get_processor_checksums).unwrap_or_default()
+  }
+
+  /// Where the processors extract their data files, None for installers without processors
+  pub fn get_temp_dir(&self) -> Option<&PathBuf> {
+    self.processors.as_ref().map(PostProcessors::get_temp_dir)
+  }
+
+  /// Overrides the system temp directory for the processors' data files, see [`PostProcessors::set_temp_dir`]
+  pub fn set_temp_dir(&mut self, temp_dir: PathBuf) {
+    if let Some(processors) = self.processors.as_mut() {
+      processors.set_temp_dir(temp_dir);
+    }
+  }
+
+  /// Whether the processors' data files are kept after the install, None for installers without processors
+  pub fn get_keep_temp_dir(&self) -> Option<bool> {
+    self.processors.as_ref().map(PostProcessors::get_keep_temp_dir)
+  }
+
+  pub fn set_keep_temp_dir(&mut self, keep_temp_dir: bool) {
+    if let Some(processors) = self.processors.as_mut() {
+      processors.set_keep_temp_dir(keep_temp_dir);
+    }
+  }
+
+  /// Java options of the processors, None for installers without processors
+  pub fn get_jvm_options(&self) -> Option<&JvmOptions> {
+    self.processors.as_ref().map(PostProcessors::get_jvm_options)
+  }
+
+  pub fn set_jvm_options(&mut self, jvm_options: JvmOptions) {
+    if let Some(processors) = self.processors.as_mut() {
+      processors.set_jvm_options(jvm_options);
+    }
+  }
+
+  /// Hook deciding whether each processor runs, None for installers without processors
+  pub fn get_on_before_processor(&self) -> Option<&ProcessorHook> {
+    self.processors.as_ref().and_then(PostProcessors::get_on_before_processor)
+  }
+
+  /// See [`PostProcessors::set_on_before_processor`]
+  pub fn set_on_before_processor(&mut self, on_before_processor: Option<ProcessorHook>) {
+    if let Some(processors) = self.processors.as_mut() {
+      processors.set_on_before_processor(on_before_processor);
+    }
+  }
+
+  pub fn get_keep_inherits_from(&self) -> bool {
+    self.keep_inherits_from
+  }
+
+  /// When true (the default) the installed version json keeps `inheritsFrom` pointing at the vanilla
+  /// version, so the launcher merges both jsons at launch. When false it's flattened into a standalone
+  /// json that already contains the vanilla libraries and arguments, for launchers without inheritance.
+  pub fn set_keep_inherits_from(&mut self, keep_inherits_from: bool) {
+    self.keep_inherits_from = keep_inherits_from;
+  }
+
+  pub fn get_dry_run(&self) -> bool {
+    self.dry_run
+  }
+
+  /// When true, [`Self::install_forge`] only decides what it would do and returns it as the report's
+  /// `planned_actions`, without writing anything or going online
+  pub fn set_dry_run(&mut self, dry_run: bool) {
+    self.dry_run = dry_run;
+  }
+
+  /// Minecraft version whose vanilla jar and json are installed, the one the profile targets unless overridden
+  pub fn get_minecraft_version(&self) -> String {
+    self.minecraft_version.clone().unwrap_or_else(|| self.profile.get_minecraft())
+  }
+
+  /// Installs the vanilla jar and json of `minecraft_version` instead of the version the profile targets, and
+  /// passes it to the processors as `MINECRAFT_VERSION` (their `MINECRAFT_JAR` follows). This is for
+  /// bootstrapping setups only: forge patches a specific vanilla jar and an incompatible one breaks the game.
+  pub fn set_minecraft_version(&mut self, minecraft_version: Option<String>) {
+    if let Some(minecraft_version) = minecraft_version.as_ref().filter(|version| **version != self.profile.get_minecraft()) {
+      warn!("Overriding the minecraft version of {} ({}) with {minecraft_version}, the install may not work", self.profile.get_version_id(), self.profile.get_minecraft());
+    }
+    if let Some(processors) = self.processors.as_mut() {
+      processors.set_minecraft_version(minecraft_version.clone());
+    }
+    self.minecraft_version = minecraft_version;
+  }
+
+  pub fn get_skip_vanilla(&self) -> bool {
+    self.skip_vanilla
+  }
+
+  /// When true, the vanilla client jar is expected to be managed by the launcher: the install fails if
+  /// it's missing from `versions/<minecraft>/` instead of downloading it
+  pub fn set_skip_vanilla(&mut self, skip_vanilla: bool) {
+    self.skip_vanilla = skip_vanilla;
+  }
+
+  /// Lists the libraries and processor jars an install for `side` ("client" or "server") would need,
+  /// without touching the network or the filesystem.
+  pub fn plan(&self, side: &str) -> InstallPlan {
+    let mut libraries: Vec<(Artifact, Option<u64>)> = vec![];
+    let mut processor_jars: Vec<Artifact> = vec![];
+    let mut push_library = |artifact: Artifact, size: Option<u64>| {
+      if !libraries.iter().any(|(a, _)| a == &artifact) {
+        libraries.push((artifact, size));
+      }
+    };
+
+    match self.profile.deref() {
+      ForgeInstallerProfile::V1(profile) => {
+        let marker = if side == "client" { "clientreq" } else { "serverreq" };
+        for lib in profile.get_libraries(marker, |_| true) {
+          push_library(lib.name, None);
+        }
+      }
+      ForgeInstallerProfile::V2(profile) => {
+        let (os, arch) = (&self.download_config.target_os, &self.download_config.target_arch);
+        for lib in self.version.libraries.iter().chain(profile.get_libraries()) {
+          match lib {
+            ForgeVersionLibrary::Mojang(lib) if !lib.is_allowed(os, arch) => {}
+            ForgeVersionLibrary::Mojang(lib) => {
+              let native = lib.get_native(os, arch);
+              if lib.downloads.artifact.is_some() || native.is_none() {
+                push_library(lib.name.clone(), lib.downloads.artifact.as_ref().and_then(|a| a.size));
+              }
+              if let Some((native, download)) = native {
+                push_library(native, download.size);
+              }
+            }
+            ForgeVersionLibrary::Forge(lib) => push_library(lib.name.clone(), None),
+          }
+        }
+        for processor in profile.get_processors(side) {
+          for jar in std::iter::once(&processor.jar).chain(&processor.classpath) {
+            if !processor_jars.iter().any(|a| a == jar) {
+              processor_jars.push(jar.clone());
+            }
+          }
+        }
+      }
+    }
+
+    let total_known_bytes = libraries
+      .iter()
+      .filter_map(|(_, size)| *size)
+      .sum();
+    InstallPlan { libraries, processor_jars, total_known_bytes }
+  }
+
+  pub async fn is_installed(&self, mc_dir: &Path) -> bool {
+    self.get_install_status(mc_dir).await == InstallStatus::Complete
+  }
+
+  /// Checks the version json and every client library required by the profile against the files in
+  /// `mc_dir`, so only the corrupt libraries need to be repaired. Libraries are hashed with
+  /// [`Sha1Sum::from_file_async`].
+  pub async fn get_install_status(&self, mc_dir: &Path) -> InstallStatus {
+    let version_id = self.profile.get_version_id();
+    let version_json = mc_dir.join("versions").join(&version_id).join(format!("{version_id}.json"));
+    let parsed = fs::read(&version_json).ok().and_then(|bytes| serde_json::from_slice::<ForgeVersionInfo>(&bytes).ok());
+    if parsed.is_none() {
+      return InstallStatus::Missing;
+    }
+
+    let libraries_root_dir = mc_dir.join("libraries");
+    let is_valid = |artifact: &Artifact, checksums: Vec<Sha1Sum>| {
+      let path = artifact.get_local_path(&libraries_root_dir);
+      async move {
+        if !path.is_file() {
+          return false;
+        }
+        if checksums.is_empty() {
+          return true;
+        }
+        Sha1Sum::from_file_async(&path).await.is_ok_and(|sum| checksums.contains(&sum))
+      }
+    };
+
+    let mut corrupt = vec![];
+    match self.profile.deref() {
+      ForgeInstallerProfile::V1(profile) => {
+        let libraries = self.version.libraries.iter().filter_map(ForgeVersionLibrary::to_forge);
+        for lib in libraries.filter(|lib| lib.is_side("clientreq")) {
+          if !is_valid(&lib.name, lib.checksums.clone()).await {
+            corrupt.push(lib.name.clone());
+          }
+        }
+        if !is_valid(&profile.install.path, vec![]).await {
+          corrupt.push(profile.install.path.clone());
+        }
+      }
+      ForgeInstallerProfile::V2(profile) => {
+        let libraries = self.version.libraries
+          .iter()
+          .chain(profile.get_libraries())
+          .filter_map(ForgeVersionLibrary::to_mojang)
+          .filter(|lib| lib.is_allowed(&self.download_config.target_os, &self.download_config.target_arch));
+        for lib in libraries {
+          let native = lib.get_native(&self.download_config.target_os, &self.download_config.target_arch);
+          if lib.downloads.artifact.is_some() || native.is_none() {
+            let checksums = lib.downloads.artifact.as_ref().and_then(|a| a.sha1.clone()).into_iter().collect::<Vec<_>>();
+            if !is_valid(&lib.name, checksums).await && !corrupt.contains(&lib.name) {
+              corrupt.push(lib.name.clone());
+            }
+          }
+          if let Some((artifact, download)) = native {
+            if !is_valid(&artifact, download.sha1.into_iter().collect()).await && !corrupt.contains(&artifact) {
+              corrupt.push(artifact);
+            }
+          }
+        }
+      }
+    }
+    if corrupt.is_empty() { InstallStatus::Complete } else { InstallStatus::Corrupt(corrupt) }
+  }
+
+  /// Ordered jars to put on the classpath to launch the installed version: every library of the version json
+  /// allowed on the target os, followed by the client jar. For versions that inherit from vanilla the client
+  /// jar is the vanilla one, whose own libraries aren't included.
+  pub fn launch_classpath(&self, mc_dir: &Path) -> Vec<PathBuf> {
+    let libraries_dir = mc_dir.join("libraries");
+    let (os, arch) = (&self.download_config.target_os, &self.download_config.target_arch);
+    let mut classpath = vec![];
+    for library in &self.version.libraries {
+      match library {
+        ForgeVersionLibrary::Mojang(lib) => {
+          // Native only libraries are extracted, not put on the classpath
+          if lib.is_allowed(os, arch) && (lib.downloads.artifact.is_some() || lib.get_native(os, arch).is_none()) {
+            classpath.push(lib.name.get_local_path(&libraries_dir));
+          }
+        }
+        ForgeVersionLibrary::Forge(lib) => classpath.push(lib.name.get_local_path(&libraries_dir)),
+      }
+    }
+    let jar = self.version.jar.clone().or(self.version.inherits_from.clone()).unwrap_or(self.profile.get_version_id());
+    classpath.push(mc_dir.join("versions").join(&jar).join(format!("{jar}.jar")));
+    classpath
+  }
+
+  /// Files and folders written by the last install, not counting the libraries in `grabbed`. Ones that were
+  /// already there and got overwritten aren't included.
+  pub fn get_created_files(&self) -> &Vec<PathBuf> {
+    &self.created_files
+  }
+
+  /// Same as [`Self::install_forge`], but rolls back with [`Self::uninstall`] if the install fails. The rollback
+  /// runs before the [`InstallLock`] is released, so no other install sees the half written files.
+  pub async fn install_forge_transactional(&mut self, mc_dir: &Path, optionals: impl Fn(&str) -> bool) -> Result<InstallReport, Box<dyn Error>> {
+    // Failing to lock installs nothing, and the files belong to the install holding the lock
+    let _lock = self.lock(mc_dir)?;
+    let result = self.install_forge_unlocked(mc_dir, optionals).await;
+    if let Err(err) = &result {
+      warn!("Install failed, rolling back: {err}");
+      if let Err(err) = self.uninstall(mc_dir) {
+        error!("Failed to roll back install: {err}");
+      }
+    }
+    result
+  }
+
+  /// Removes the files and folders created by the last install and the libraries it grabbed. Anything that was
+  /// already there (a version folder being repaired, libraries or processor outputs shared with the vanilla
+  /// install or other Forge versions) isn't recorded, so it's left alone.
+  pub fn uninstall(&self, mc_dir: &Path) -> Result<(), Box<dyn Error>> {
+    let libraries_root_dir = mc_dir.join("libraries");
+
+    let mut files = self.created_files.clone();
+    files.extend(self.grabbed.iter().map(|artifact| artifact.get_local_path(&libraries_root_dir)));
+    for file in files {
+      if file.is_file() {
+        info!("Removing {}", file.display());
+        fs::remove_file(&file)?;
+      } else if file.is_dir() {
+        info!("Removing {}", file.display());
+        fs::remove_dir_all(&file)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Optional client mods of a V1 profile, V2 profiles have none
+  pub fn optional_mods(&self) -> Vec<&ForgeOptional> {
+    match self.profile.deref() {
+      ForgeInstallerProfile::V1(profile) => profile.get_optionals("client"),
+      ForgeInstallerProfile::V2(_) => vec![],
+    }
+  }
+
+  /// Descriptors of the optional mods that are enabled by default, a starting point for a selection
+  pub fn get_default_optionals(&self) -> HashSet<String> {
+    self.optional_mods()
+      .into_iter()
+      .filter(|opt| opt.default)
+      .map(|opt| opt.artifact.get_descriptor())
+      .collect()
+  }
+
+  /// Same as [`Self::install_forge`], installing only the optional mods whose artifact descriptor is in `selected`
+  pub async fn install_forge_with_optionals(&mut self, mc_dir: &Path, selected: &HashSet<String>) -> Result<InstallReport, Box<dyn Error>> {
+    self.install_forge(mc_dir, |descriptor| selected.contains(descriptor)).await
+  }
+
+  /// Provisions a game directory from scratch: downloads the vanilla version json and every vanilla library
+  /// for the target os, then installs Forge with its default optional mods. `progress` receives the
+  /// downloads of both steps. Libraries shared by both are only fetched once. A dry run, or skipping the
+  /// vanilla downloads, leaves out the vanilla libraries.
+  pub async fn install_with_vanilla(&mut self, mc_dir: &Path, progress: ProgressCallback) -> Result<InstallReport, Box<dyn Error>> {
+    let previous_progress = self.download_config.on_progress.replace(progress);
+    let result = self.install_with_vanilla_inner(mc_dir).await;
+    self.download_config.on_progress = previous_progress;
+    result
+  }
+
+  async fn install_with_vanilla_inner(&mut self, mc_dir: &Path) -> Result<InstallReport, Box<dyn Error>> {
+    let _lock = self.lock(mc_dir)?;
+    if !self.dry_run && !self.skip_vanilla {
+      let minecraft = self.get_minecraft_version();
+      let version_vanilla = mc_dir.join("versions").join(&minecraft);
+      create_dir_all(&version_vanilla)?;
+      let version_json = version_vanilla.join(format!("{minecraft}.json"));
+      let vanilla = self.get_vanilla_version(&minecraft, &version_json).await?;
+      let Some(vanilla) = vanilla else {
+        Err(forge_err!("Failed to download the {minecraft} version json."))?
+      };
+      let libraries = vanilla["libraries"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|library| serde_json::from_value::<MojangLibrary>(library.clone()).ok())
+        .collect::<Vec<_>>();
+
+      info!("Downloading {} vanilla libraries...", libraries.len());
+      self.download_mojang_libraries(&libraries, &mc_dir.join("libraries"), &|_| true).await?;
+    }
+
+    // Only optional mods are filtered, every other library (all of them for V2 profiles) is required
+    let optional = self.optional_mods().into_iter().map(|opt| opt.artifact.get_descriptor()).collect::<HashSet<_>>();
+    let defaults = self.get_default_optionals();
+    self.install_forge_unlocked(mc_dir, |descriptor| !optional.contains(descriptor) || defaults.contains(descriptor)).await
+  }
+
+  /// Installs into `mc_dir` while holding its [`InstallLock`], dry runs don't take it
+  pub async fn install_forge(&mut self, mc_dir: &Path, /* installer */ optionals: impl Fn(&str) -> bool) -> Result<InstallReport, Box<dyn Error>> {
+    let _lock = self.lock(mc_dir)?;
+    self.install_forge_unlocked(mc_dir, optionals).await
+  }
+
+  /// Starts an install: takes the [`InstallLock`] and forgets the libraries grabbed by the last one
+  fn lock(&mut self, mc_dir: &Path) -> Result<Option<InstallLock>, Box<dyn Error>> {
+    self.grabbed = HashSet::new();
+    if self.dry_run {
+      return Ok(None);
+    }
+    create_dir_all(mc_dir)?;
+    Ok(Some(InstallLock::acquire(mc_dir)?))
+  }
+
+  async fn install_forge_unlocked(&mut self, mc_dir: &Path, optionals: impl Fn(&str) -> bool) -> Result<InstallReport, Box<dyn Error>> {
+    let started = Instant::now();
+    if self.dry_run {
+      return self.plan_install(mc_dir, &optionals, started).await;
+    }
+    let mut bytes_downloaded = 0;
+    let mut processors_run = 0;
+    let mut processor_outputs = HashMap::new();
+    let mut extracted_from_installer = vec![];
+    self.created_files = vec![];
+    create_dir_all(mc_dir)?;
 
     let versions_root_dir = mc_dir.join("versions");
     create_dir_all(&versions_root_dir)?;
@@ -79,7 +990,8 @@ impl ForgeClientInstall {
     create_dir_all(&libraries_root_dir)?;
 
     // Check install_version version
-    let version_dir = versions_root_dir.join(&self.profile.get_version_id());
+    let version_dir = versions_root_dir.join(self.profile.get_version_id());
+    self.record_created(&version_dir);
     if create_dir_all(&version_dir).is_err() && !version_dir.is_dir() {
       if fs::remove_dir_all(&version_dir).is_err() {
         Err(forge_err!("Failed to clear version folder. You will need to clear {} manually.", version_dir.display()))?;
@@ -94,23 +1006,25 @@ impl ForgeClientInstall {
         debug!("Profile manifest version: v1");
 
         let mut profile = profile.clone();
-        // println!("📦 Extracting version.json from installer_profile.json...");
-        let libraries = profile.get_libraries("clientreq", optionals);
-        let minecraft_jar_file = self.download_vanilla_client_jar(&versions_root_dir).await?;
+        debug!("Extracting version.json from install_profile.json...");
+        let libraries = profile.get_libraries("clientreq", &optionals);
+        let (minecraft_jar_file, client_bytes) = self.download_vanilla_client_jar_counted(&versions_root_dir).await?;
+        bytes_downloaded += client_bytes;
         if !profile.is_inherited_json() {
           let client_jar_file = version_dir.join(format!("{}.jar", &self.profile.get_version_id()));
           if profile.install.strip_meta.is_some_and(|strip_meta| strip_meta) {
             info!("Copying and filtering minecraft client jar");
-            self.copy_and_strip(&minecraft_jar_file, &client_jar_file)?;
+            self.record_created(&client_jar_file);
+            copy_and_strip(&minecraft_jar_file, &client_jar_file)?;
           } else {
             info!("Copying minecraft client jar");
+            self.record_created(&client_jar_file);
             fs::copy(minecraft_jar_file, client_jar_file)?;
           }
         }
         let target_library_file = profile.install.path.get_local_path(&libraries_root_dir);
-        self.grabbed = vec![];
-        let mut bad = vec![];
-        download_utils::download_installed_libraries(true, &libraries_root_dir, &libraries, &mut self.grabbed, &mut bad, &mut self.archive).await?;
+        let (bad, downloaded) = download_utils::download_installed_libraries(true, &libraries_root_dir, &libraries, &mut self.grabbed, &mut self.source, &self.download_config, &self.cancellation_token).await?;
+        bytes_downloaded += downloaded;
         if bad.len() > 0 {
           let list = bad
             .iter()
@@ -139,7 +1053,8 @@ impl ForgeClientInstall {
           }
           "none" => {/* Do nothing*/}
           _ => {
-            if ForgeOptional::save_mod_list_json(&libraries_root_dir, &mod_list_file, &profile.optionals, optionals).is_err() {
+            self.record_created(&mod_list_file);
+            if ForgeOptional::save_mod_list_json(&libraries_root_dir, &mod_list_file, &profile.optionals, &optionals).is_err() {
               Err(forge_err!("Failed to write mod_list.json, optional mods may not be loaded."))?;
             }
           }
@@ -170,23 +1085,28 @@ impl ForgeClientInstall {
           .for_each(|lib| lst.push(lib));
         output.libraries = lst;
         info!("Writing to {}", version_json_file.display());
-        serde_json::to_writer_pretty(File::create(&version_json_file)?, &output)?;
+        self.write_version_json(&version_json_file, &output, &versions_root_dir).await?;
 
         // Extract file
-        let contained_file = &mut self.archive.by_name(&profile.install.file_path)?;
+        self.record_created(&target_library_file);
+        let contained_file = &mut self.source.by_name(&profile.install.get_file_path())?;
         io::copy(contained_file, &mut File::create(target_library_file)?)?;
+        extracted_from_installer.push(profile.install.path.clone());
       }
       ForgeInstallerProfile::V2(_) => {
         debug!("Profile manifest version: v2");
         info!("Extracting version.json...");
 
-        let mut file = File::create(version_json)?;
-        let bytes = &serde_json::to_vec_pretty(&self.version)?[..];
-        file.write_all(bytes)?;
-
-        info!("{} bytes were extracted!", bytes.len());
+        let mut version = self.version.clone();
+        if version.inherits_from.is_none() {
+          version.inherits_from = Some(self.get_minecraft_version());
+        }
+        let bytes = self.write_version_json(&version_json, &version, &versions_root_dir).await?;
+        info!("{bytes} bytes were extracted!");
 
-        let client_target = self.download_vanilla_client_jar(&versions_root_dir).await?;
+        let (client_target, client_bytes) = self.download_vanilla_client_jar_counted(&versions_root_dir).await?;
+        bytes_downloaded += client_bytes;
+        self.cancellation_token.check()?;
 
         //     // TODO: get mirror?
         //     let bytes = Client::new().get(client).send().await?.bytes().await?;
@@ -195,49 +1115,243 @@ impl ForgeClientInstall {
         //     fs::write(&client_target, bytes)?;
         // }
 
-        if let Err(err) = self.download_libraries(&libraries_root_dir, optionals, vec![]).await {
-          error!("{err}");
-          return Err(Box::new(std::io::Error::new(ErrorKind::Other, "Could not download libraries.")));
+        match self.download_libraries(&libraries_root_dir, &optionals).await {
+          Ok(bytes) => bytes_downloaded += bytes,
+          Err(err) => {
+            self.cancellation_token.check()?;
+            error!("{err}");
+            // Typed errors, like a library missing from an offline install, are what the caller matches on
+            if err.downcast_ref::<ForgeInstallError>().is_some() {
+              return Err(err);
+            }
+            return Err(Box::new(std::io::Error::new(ErrorKind::Other, "Could not download libraries.")));
+          }
         }
 
-        let processors = self.processors.as_mut().unwrap();
-        if let Err(err) = processors.process(&libraries_root_dir, &client_target, &mc_dir, &self.installer_path, &mut self.archive).await {
-          error!("{err}");
-          return Err(Box::new(std::io::Error::new(ErrorKind::Other, "Could not process libraries.")));
+        // Outputs another install of this minecraft version already built are shared, only new ones are recorded
+        if let ForgeInstallerProfile::V2(profile) = self.profile.clone().deref() {
+          for value in profile.get_data(true).values() {
+            if value.starts_with('[') && value.ends_with(']') {
+              if let Ok(artifact) = Artifact::try_from(value[1..value.len() - 1].to_string()) {
+                self.record_created(&artifact.get_local_path(&libraries_root_dir));
+              }
+            }
+          }
+        }
+        if let Some(processors) = self.processors.as_mut() {
+          let result = processors.process(&libraries_root_dir, &client_target, mc_dir, self.installer_path.as_ref(), &mut self.source, &self.cancellation_token).await;
+          match result {
+            Ok(outputs) => {
+              processors_run = outputs.iter().filter(|output| output.ran).count();
+              processor_outputs = outputs.into_iter().flat_map(|output| output.outputs).collect();
+            }
+            Err(err) => {
+              self.cancellation_token.check()?;
+              error!("{err}");
+              // Keeps the rejected processor, or the exit code and stderr of the one that failed
+              if err.downcast_ref::<ForgeInstallError>().is_some() {
+                return Err(err);
+              }
+              return Err(Box::new(std::io::Error::new(ErrorKind::Other, "Could not process libraries.")));
+            }
+          }
+        } else {
+          info!("No processors to run");
         }
       }
     }
     info!("Successfully installed version {} and grabbed {} required libraries", self.profile.get_version_id(), self.grabbed.len());
-    Ok(())
+    let mut grabbed = self.grabbed.iter().cloned().collect::<Vec<_>>();
+    grabbed.sort_by_key(|artifact| artifact.get_descriptor());
+    for artifact in &grabbed {
+      let path = artifact.get_path_string();
+      let packed = self.source.by_name(&format!("maven/{path}")).is_ok() || self.source.by_name(&path).is_ok();
+      if packed && !extracted_from_installer.contains(artifact) {
+        extracted_from_installer.push(artifact.clone());
+      }
+    }
+    Ok(InstallReport {
+      version_id: self.profile.get_version_id(),
+      minecraft_version: self.get_minecraft_version(),
+      grabbed,
+      extracted_from_installer,
+      processors_run,
+      processor_outputs,
+      bytes_downloaded,
+      duration: started.elapsed(),
+      planned_actions: vec![],
+    })
+  }
+
+  /// Dry run of [`Self::install_forge`], only reads the disk and the installer archive
+  async fn plan_install(&mut self, mc_dir: &Path, optionals: &dyn Fn(&str) -> bool, started: Instant) -> Result<InstallReport, Box<dyn Error>> {
+    let mut actions = vec![];
+    let versions_root_dir = mc_dir.join("versions");
+    let libraries_root_dir = mc_dir.join("libraries");
+    let version_id = self.profile.get_version_id();
+    let version_dir = versions_root_dir.join(&version_id);
+    for dir in [mc_dir, &versions_root_dir, &libraries_root_dir, &version_dir] {
+      if !dir.is_dir() {
+        actions.push(PlannedAction::CreateDir(dir.to_path_buf()));
+      }
+    }
+    let version_json = version_dir.join(format!("{version_id}.json"));
+    let client_jar = self.plan_vanilla_client_jar(&versions_root_dir, &mut actions);
+
+    match self.profile.deref() {
+      ForgeInstallerProfile::V1(profile) => {
+        let libraries = profile.get_libraries("clientreq", optionals);
+        if !profile.is_inherited_json() {
+          actions.push(PlannedAction::Copy { from: client_jar, to: version_dir.join(format!("{version_id}.jar")) });
+        }
+        let config = &self.download_config;
+        actions.extend(download_utils::plan_installed_libraries(true, &libraries_root_dir, &libraries, &mut self.source, config).await?);
+        match profile.install.mod_list.as_str() {
+          "absolute" | "none" => {}
+          _ => actions.push(PlannedAction::WriteFile(mc_dir.join("mods").join("mod_list.json"))),
+        }
+        actions.push(PlannedAction::WriteFile(version_json));
+        actions.push(PlannedAction::Extract {
+          entry: profile.install.get_file_path(),
+          target: profile.install.path.get_local_path(&libraries_root_dir),
+        });
+      }
+      ForgeInstallerProfile::V2(_) => {
+        actions.push(PlannedAction::WriteFile(version_json));
+        let mut libraries = self.version.libraries.iter().collect::<Vec<_>>();
+        libraries.extend(self.processors.as_ref().map(PostProcessors::get_libraries).unwrap_or_default());
+        let mut seen = HashSet::new();
+        libraries.retain(|lib| seen.insert(lib.get_name().clone()));
+        let additional_lib_dirs = self.download_config.additional_library_dirs.iter().collect::<Vec<_>>();
+        for lib in libraries {
+          if let ForgeVersionLibrary::Mojang(lib) = lib {
+            let config = &self.download_config;
+            actions.extend(download_utils::plan_library(&mut self.source, lib, &libraries_root_dir, optionals, &additional_lib_dirs, config).await?);
+          }
+        }
+        if let Some(processors) = self.processors.as_ref() {
+          actions.extend(processors.plan(&libraries_root_dir, &client_jar, mc_dir, self.installer_path.as_ref(), &mut self.source)?);
+        }
+      }
+    }
+    info!("Dry run of version {version_id} planned {} actions", actions.len());
+    Ok(InstallReport {
+      version_id,
+      minecraft_version: self.get_minecraft_version(),
+      grabbed: vec![],
+      extracted_from_installer: vec![],
+      processors_run: 0,
+      processor_outputs: HashMap::new(),
+      bytes_downloaded: 0,
+      duration: started.elapsed(),
+      planned_actions: actions,
+    })
+  }
+
+  /// Dry run of [`Self::download_vanilla_client_jar`], returns where the client jar would be
+  fn plan_vanilla_client_jar(&self, versions_root: &Path, actions: &mut Vec<PlannedAction>) -> PathBuf {
+    let minecraft = self.get_minecraft_version();
+    let version_vanilla = versions_root.join(&minecraft);
+    let client_target = version_vanilla.join(format!("{minecraft}.jar"));
+    if !client_target.is_file() && !self.skip_vanilla {
+      if !version_vanilla.is_dir() {
+        actions.push(PlannedAction::CreateDir(version_vanilla.clone()));
+      }
+      // Without going online, only an already cached version json can tell the url
+      let version_json = version_vanilla.join(format!("{minecraft}.json"));
+      let url = fs::read(&version_json)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok())
+        .and_then(|vanilla| vanilla["downloads"]["client"]["url"].as_str().map(str::to_string));
+      if url.is_none() {
+        actions.push(PlannedAction::WriteFile(version_json));
+      }
+      actions.push(PlannedAction::Download { url, target: client_target.clone() });
+    }
+    client_target
   }
 
   async fn download_libraries(
     &mut self,
-    libraries_dir: &PathBuf,
-    optionals: fn(&str) -> bool,
-    additional_lib_dirs: Vec<&PathBuf>
-  ) -> Result<(), Box<dyn Error>> {
+    libraries_dir: &Path,
+    optionals: &dyn Fn(&str) -> bool
+  ) -> Result<u64, Box<dyn Error>> {
     info!("  Downloading libraries...");
-    info!("Found {} additional library directories", additional_lib_dirs.len());
     let mut libraries = vec![];
     libraries.extend(&self.version.libraries.iter().collect::<Vec<_>>()); // Download version libraries
-    libraries.extend(self.processors.as_ref().unwrap().get_libraries()); // Download profile libraries
+    libraries.extend(self.processors.as_ref().map(PostProcessors::get_libraries).unwrap_or_default()); // Download profile libraries
+    // Both lists share most of their libraries (the forge jar, asm...), each one is only considered once
+    let mut seen = HashSet::new();
+    libraries.retain(|lib| seen.insert(lib.get_name().clone()));
+    let libraries = libraries
+      .into_iter()
+      .filter_map(|lib| match lib {
+        ForgeVersionLibrary::Mojang(lib) => Some(lib.clone()),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    self.download_mojang_libraries(&libraries, libraries_dir, optionals).await
+  }
+
+  /// Places `libraries` into `libraries_dir`, downloading up to [`DownloadConfig::max_concurrent_downloads`]
+  /// of them at once. Returns the number of bytes downloaded.
+  async fn download_mojang_libraries(
+    &mut self,
+    libraries: &[MojangLibrary],
+    libraries_dir: &Path,
+    optionals: &dyn Fn(&str) -> bool
+  ) -> Result<u64, Box<dyn Error>> {
+    let additional_lib_dirs = self.download_config.additional_library_dirs.iter().collect::<Vec<_>>();
+    info!("Found {} additional library directories", additional_lib_dirs.len());
     let mut output = String::new();
     let steps = libraries.len();
     let mut progress = 1;
+    // Extracting from the installer archive needs exclusive access to it, so every library is first
+    // resolved serially. Only the ones left for the network are then downloaded concurrently.
+    let mut pending = vec![];
+    let config = &self.download_config;
+    let cancel = &self.cancellation_token;
     for lib in libraries {
-      if let ForgeVersionLibrary::Mojang(lib) = lib {
-        info!("Downloading library {progress}/{steps}...");
-        progress += 1;
-        if download_library(&mut self.archive, lib, libraries_dir, optionals, &mut self.grabbed, &additional_lib_dirs).await.is_err() {
-          let download = lib.downloads.artifact.as_ref();
-          // .as_ref()
-          // .and_then(|downloads| downloads.artifact.as_ref());
-          if let Some(download) = download {
-            if download.url.as_ref().is_some_and(|url| !url.is_empty()) {
-              output.push_str(&format!("\n{}", lib.name.get_descriptor()));
-            }
-          }
+      cancel.check()?;
+      info!("Downloading library {progress}/{steps}...");
+      progress += 1;
+      // Already placed by an earlier step of this install, like the vanilla libraries shared with Forge
+      if self.grabbed.contains(&lib.name) {
+        continue;
+      }
+      match resolve_library(&mut self.source, lib, libraries_dir, optionals, &mut self.grabbed, &additional_lib_dirs, config).await {
+        Ok(artifacts) => pending.extend(artifacts),
+        Err(_) => Self::push_failed_library(&mut output, lib),
+      }
+    }
+    if config.offline && !pending.is_empty() {
+      let missing = pending.iter().map(|(artifact, _, _)| artifact.get_descriptor()).collect::<Vec<_>>();
+      Err(ForgeInstallError::OfflineMissing(missing.join(", ")))?;
+    }
+
+    let results = stream::iter(pending)
+      .map(|(artifact, download, target)| async move {
+        if cancel.is_cancelled() {
+          return (artifact, Err(Box::new(ForgeInstallError::Cancelled) as Box<dyn Error>));
+        }
+        let download = config.override_download(&artifact, download);
+        let result = download_library_artifact(&download, &target, config).await;
+        (artifact, result.map(|_| fs::metadata(&target).map(|metadata| metadata.len()).unwrap_or(0)))
+      })
+      .buffer_unordered(config.max_concurrent_downloads.max(1))
+      .collect::<Vec<_>>()
+      .await;
+    cancel.check()?;
+    let mut downloaded = 0;
+    for (artifact, result) in results {
+      match result {
+        Ok(bytes) => {
+          downloaded += bytes;
+          self.grabbed.insert(artifact);
+        }
+        Err(err) => {
+          error!("Failed to download library {}: {err}", artifact.get_descriptor());
+          output.push_str(&format!("\n{}", artifact.get_descriptor()));
         }
       }
     }
@@ -245,23 +1359,95 @@ impl ForgeClientInstall {
     if !output.is_empty() {
       Err(Box::new(std::io::Error::new(ErrorKind::Other, format!("These libraries failed to download. Try again.\n{}", output))))
     } else {
-      Ok(())
+      Ok(downloaded)
+    }
+  }
+
+  fn push_failed_library(output: &mut String, lib: &MojangLibrary) {
+    let download = lib.downloads.artifact.as_ref();
+    if let Some(download) = download {
+      if download.url.as_ref().is_some_and(|url| !url.is_empty()) {
+        output.push_str(&format!("\n{}", lib.name.get_descriptor()));
+      }
+    }
+  }
+
+  /// [`get_vanilla_version_with_cache`] after [`DownloadConfig::vanilla_version_json`], only reading the disk when offline
+  async fn get_vanilla_version(&self, mc_version: &str, json_path: &Path) -> Result<Option<serde_json::Value>, ForgeInstallError> {
+    let config = &self.download_config;
+    if let Some(vanilla) = config.vanilla_version_json.as_ref().filter(|vanilla| vanilla["id"].as_str() == Some(mc_version)) {
+      info!("Using the supplied {mc_version} version json");
+      return Ok(Some(vanilla.clone()));
+    }
+    if config.offline {
+      // A bad cached json is left alone, replacing it would need the network
+      let bytes = fs::read(json_path).map_err(|_| ForgeInstallError::OfflineMissing(json_path.display().to_string()))?;
+      return match parse_vanilla_version(mc_version, &bytes) {
+        Some(json) => Ok(Some(json)),
+        None => Err(ForgeInstallError::InvalidProfile(format!("{} isn't a valid {mc_version} version json", json_path.display()))),
+      };
     }
+    Ok(get_vanilla_version_with_cache(mc_version, json_path, &config.manifest_cache, &config.repositories).await)
   }
 
-  pub async fn download_vanilla_client_jar(&self, versions_root: &PathBuf) -> Result<PathBuf, Box<dyn Error>> {
+  /// Writes `version` to `path`, flattened into its vanilla json unless `keep_inherits_from` is set.
+  /// Returns the number of bytes written.
+  async fn write_version_json(&mut self, path: &Path, version: &ForgeVersionInfo, versions_root: &Path) -> Result<usize, Box<dyn Error>> {
+    let bytes = match &version.inherits_from {
+      Some(inherits_from) if !self.keep_inherits_from => {
+        info!("Flattening version json into {inherits_from}");
+        let vanilla_json = versions_root.join(inherits_from).join(format!("{inherits_from}.json"));
+        let vanilla = self.get_vanilla_version(inherits_from, &vanilla_json).await?;
+        let Some(vanilla) = vanilla else {
+          Err(forge_err!("Failed to download the {inherits_from} version json, can't flatten the version json."))?
+        };
+        serde_json::to_vec_pretty(&version.flatten(&vanilla)?)?
+      }
+      _ => serde_json::to_vec_pretty(version)?,
+    };
+    self.record_created(path);
+    let mut file = File::create(path)?;
+    file.write_all(&bytes)?;
+    // Some filesystems only report a full disk once the data is flushed to it
+    file.sync_all()?;
+    Ok(bytes.len())
+  }
+
+  /// Records `path` (a file or a folder) for [`Self::uninstall`] before it's written, unless it's already there
+  fn record_created(&mut self, path: &Path) {
+    if !path.exists() {
+      self.created_files.push(path.to_path_buf());
+    }
+  }
+
+  pub async fn download_vanilla_client_jar(&self, versions_root: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(self.download_vanilla_client_jar_counted(versions_root).await?.0)
+  }
+
+  /// Same as [`Self::download_vanilla_client_jar`], also returning the number of bytes downloaded
+  async fn download_vanilla_client_jar_counted(&self, versions_root: &Path) -> Result<(PathBuf, u64), Box<dyn Error>> {
+    let mut downloaded = 0;
     info!(" Considering minecraft client jar...");
-    let version_vanilla = versions_root.join(self.profile.get_minecraft());
+    let version_vanilla = versions_root.join(self.get_minecraft_version());
+    let client_target = version_vanilla.join(format!("{}.jar", self.get_minecraft_version()));
+    if self.skip_vanilla {
+      if !client_target.is_file() {
+        Err(forge_err!("The minecraft client jar is missing at {} and downloading it is skipped.", client_target.display()))?;
+      }
+      return Ok((client_target, downloaded));
+    }
     if fs::create_dir_all(&version_vanilla).is_err() && !version_vanilla.is_dir() {
       if fs::remove_dir(&version_vanilla).is_err() {
         Err(forge_err!("There was a problem with the launcher version data. You will need to clear {} manually.", version_vanilla.display()))?;
       }
       fs::create_dir_all(&version_vanilla)?;
     }
-    let client_target = version_vanilla.join(format!("{}.jar", self.profile.get_minecraft()));
     if !client_target.is_file() {
-      let version_json = version_vanilla.join(format!("{}.json", &self.profile.get_minecraft()));
-      let vanilla = get_vanilla_version(&self.profile.get_minecraft(), &version_json).await;
+      if self.download_config.offline {
+        Err(ForgeInstallError::OfflineMissing(client_target.display().to_string()))?;
+      }
+      let version_json = version_vanilla.join(format!("{}.json", &self.get_minecraft_version()));
+      let vanilla = self.get_vanilla_version(&self.get_minecraft_version(), &version_json).await?;
       if vanilla.is_none() {
         Err(forge_err!("Failed to download version manifest, can not find client jar URL."))?;
       }
@@ -270,29 +1456,57 @@ impl ForgeClientInstall {
       if client.is_none() {
         Err(forge_err!("Failed to download minecraft client, info missing from manifest: {}", version_json.display()))?;
       }
-      let client = client.unwrap()["url"].as_str().unwrap();
+      let client = client.unwrap();
+      let url = client["url"].as_str().unwrap();
+      let checksums = client["sha1"]
+        .as_str()
+        .and_then(|sha1| Sha1Sum::try_from(sha1.to_string()).ok())
+        .map(Checksum::from)
+        .into_iter()
+        .collect::<Vec<_>>();
+      if checksums.is_empty() {
+        warn!("No checksum for the minecraft client in {}, it won't be validated", version_json.display());
+      }
 
       // TODO: get mirror?
-      let bytes = Client::new().get(client).send().await?.bytes().await?;
-      // TODO: check sha1
-      // "Downloading minecraft client failed, invalid checksum.\nTry again, or use the vanilla launcher to install the vanilla version."
-      fs::write(&client_target, bytes)?;
+      let config = &self.download_config;
+      let result = download_utils::download_file_with_retries(&client_target, url, &checksums, client["size"].as_u64(), config).await;
+      if let Err(err) = result {
+        if let Some(ForgeInstallError::Checksum(sum)) = err.downcast_ref::<ForgeInstallError>() {
+          Err(
+            ForgeInstallError::Checksum(
+              format!("Downloading minecraft client failed, invalid checksum ({sum}).\nTry again, or use the vanilla launcher to install the vanilla version.")
+            )
+          )?;
+        }
+        return Err(err);
+      }
+      downloaded = fs::metadata(&client_target)?.len();
     }
-    Ok(client_target)
+    Ok((client_target, downloaded))
   }
+}
 
-  fn copy_and_strip(&self, source_jar: &PathBuf, target_jar: &PathBuf) -> Result<(), Box<dyn Error>> {
-    let mut zip_in = ZipArchive::new(File::open(source_jar)?)?;
-    let mut zip_out = ZipWriter::new(File::create(target_jar)?);
-    for i in 0..zip_in.len() {
-      let mut file = zip_in.by_index(i)?;
-      if file.name().starts_with("META-INF") {
-        continue;
-      }
-      zip_out.start_file(file.name(), FileOptions::default().last_modified_time(file.last_modified()))?;
-      io::copy(&mut file, &mut zip_out)?;
+/// Copies the jar without its signature, so the patched classes don't fail verification. The rest of
+/// `META-INF`, like the manifest and the service loader files, is kept.
+pub(crate) fn copy_and_strip(source_jar: &Path, target_jar: &Path) -> Result<(), Box<dyn Error>> {
+  let mut zip_in = ZipArchive::new(File::open(source_jar)?)?;
+  let mut zip_out = ZipWriter::new(File::create(target_jar)?);
+  for i in 0..zip_in.len() {
+    // Copied without decompressing, which keeps each entry's compression, time and permissions
+    let file = zip_in.by_index_raw(i)?;
+    if is_signature_file(file.name()) {
+      continue;
     }
-    zip_out.finish()?;
-    Ok(())
+    zip_out.raw_copy_file(file)?;
   }
+  zip_out.finish()?;
+  Ok(())
+}
+
+fn is_signature_file(name: &str) -> bool {
+  let Some(file) = name.strip_prefix("META-INF/") else {
+    return false;
+  };
+  !file.contains('/') && (file.ends_with(".SF") || file.ends_with(".RSA") || file.ends_with(".DSA") || file.starts_with("SIG-"))
 }
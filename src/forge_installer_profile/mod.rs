@@ -1,13 +1,16 @@
-use std::{ collections::HashMap, io::{ Read, Seek }, path::Path };
+use std::{ collections::{ HashMap, HashSet }, io::{ Cursor, Read, Seek }, path::Path };
 
 use chrono::{ DateTime, Utc };
 use log::debug;
-use serde::{ Deserialize, Serialize };
+use serde::{ de, Deserialize, Deserializer, Serialize };
 use serde_json::Value;
-use zip::{ result::ZipError, ZipArchive };
+use zip::ZipArchive;
+use crate::{ forge_client_install::ForgeInstallError, Artifact };
 
-use self::{ v1::{ ForgeInstallerProfileV1, ForgeLibrary }, v2::{ ForgeInstallerProfileV2, MojangLibrary } };
+use self::{ source::ForgeInstallProfileSource, v1::ForgeLibrary, v2::MojangLibrary };
 
+pub mod source;
+pub mod tokens;
 pub mod v1;
 pub mod v2;
 
@@ -19,23 +22,22 @@ pub enum ForgeInstallerProfile {
 }
 
 impl ForgeInstallerProfile {
-  pub fn from_reader<T: Read>(mut reader: T) -> Self {
+  /// Picks the profile version from its top level keys, V2 profiles have a `spec`, V1 profiles an
+  /// `install` section, so the document is only deserialized once. A leading UTF-8 BOM, left by some
+  /// repackaged installers, is skipped.
+  pub fn from_reader<T: Read>(mut reader: T) -> Result<Self, ForgeInstallError> {
     let mut bytes = vec![];
-    reader.read_to_end(&mut bytes).unwrap();
-    let result = serde_json::from_slice::<ForgeInstallerProfileV1>(bytes.as_slice()).map(|v| Self::V1(v));
-    let result2 = serde_json::from_slice::<ForgeInstallerProfileV2>(bytes.as_slice()).map(|v| Self::V2(v));
-
-    if result.is_err() && result2.is_err() {
-      debug!("");
-      if let Err(err) = &result {
-        debug!("Error V1: {}", err);
-      }
-      if let Err(err) = &result2 {
-        debug!("Error V2: {}", err);
-      }
-      panic!("Couldn't parse installer profile");
+    reader.read_to_end(&mut bytes).map_err(|err| ForgeInstallError::InvalidProfile(err.to_string()))?;
+    let json = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+    let value: Value = serde_json::from_slice(json).map_err(|err| ForgeInstallError::InvalidProfile(err.to_string()))?;
+    if value.get("spec").is_some() {
+      serde_json::from_value(value).map(Self::V2).map_err(|err| ForgeInstallError::InvalidProfile(format!("V2 profile: {err}")))
+    } else if value.get("install").is_some() {
+      serde_json::from_value(value).map(Self::V1).map_err(|err| ForgeInstallError::InvalidProfile(format!("V1 profile: {err}")))
+    } else {
+      debug!("Unknown install profile: {value}");
+      Err(ForgeInstallError::InvalidProfile("Neither a V1 (install) nor a V2 (spec) profile".to_string()))
     }
-    result.or(result2).unwrap()
   }
 
   pub fn get_version_id(&self) -> String {
@@ -52,16 +54,80 @@ impl ForgeInstallerProfile {
     }
   }
 
-  pub fn get_version_json(&self, archive: &mut ZipArchive<impl Read + Seek>) -> Result<ForgeVersionInfo, std::io::Error> {
+  /// Message the installer shows before installing
+  pub fn get_welcome(&self) -> &str {
+    match self {
+      Self::V1(profile) => &profile.install.welcome,
+      Self::V2(profile) => &profile.welcome,
+    }
+  }
+
+  /// Image at the profile's `logo` path in the installer, or its `icon` when that's a path and not an
+  /// inline `data:` url. None when the profile has neither or the file isn't in the installer.
+  pub fn get_logo_bytes(&self, source: &mut ForgeInstallProfileSource<impl Read + Seek>) -> Option<Vec<u8>> {
+    let path = match self {
+      Self::V1(profile) => Some(&profile.install.logo).filter(|logo| !logo.is_empty()),
+      Self::V2(profile) =>
+        Some(&profile.logo)
+          .filter(|logo| !logo.is_empty())
+          .or(profile.icon.as_ref().filter(|icon| !icon.starts_with("data:"))),
+    }?;
+    let mut bytes = vec![];
+    match source.by_name(path.trim_start_matches('/')).and_then(|mut file| file.read_to_end(&mut bytes)) {
+      Ok(_) => Some(bytes),
+      Err(err) => {
+        debug!("No logo at {path}: {err}");
+        None
+      }
+    }
+  }
+
+  /// Whether installing the client runs processors, and so needs java. V1 profiles are only extracted.
+  pub fn requires_processors(&self) -> bool {
+    match self {
+      Self::V1(_) => false,
+      Self::V2(profile) => !profile.get_processors("client").is_empty(),
+    }
+  }
+
+  /// The V2 version json is looked up at the profile's `json` path, falling back to its file name at the
+  /// root of the installer
+  pub fn get_version_json(&self, source: &mut ForgeInstallProfileSource<impl Read + Seek>) -> Result<ForgeVersionInfo, std::io::Error> {
     match self {
       Self::V1(profile) => Ok(profile.version_info.clone()),
+      // The declared path, then its file name and `version.json` at the root, as some installers don't
+      // store it where `json` says. As a last resort, any other json of the installer that parses.
       Self::V2(profile) => {
-        let path = Path::new(&profile.json).file_name().unwrap().to_str().unwrap().to_string();
-
-        archive
-          .by_name(&path)
-          .map_err(|err| <ZipError as Into<std::io::Error>>::into(err))
-          .and_then(|file| serde_json::from_reader(file).map_err(Into::into))
+        let mut checked = vec![profile.json.trim_start_matches('/').to_string()];
+        checked.extend(Path::new(&profile.json).file_name().and_then(|name| name.to_str()).map(str::to_string));
+        checked.push("version.json".to_string());
+        let mut seen = HashSet::new();
+        checked.retain(|path| seen.insert(path.clone()));
+        for path in &checked {
+          match source.by_name(path) {
+            Ok(file) => return serde_json::from_reader(file).map_err(Into::into),
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+            Err(_) => debug!("No version json at {path}"),
+          }
+        }
+        let mut others = source
+          .get_file_names()?
+          .into_iter()
+          .filter(|name| name.ends_with(".json") && name != "install_profile.json" && !seen.contains(name))
+          .collect::<Vec<_>>();
+        others.sort();
+        for path in &others {
+          let version = source.by_name(path).and_then(|file| serde_json::from_reader::<_, ForgeVersionInfo>(file).map_err(Into::into));
+          match version {
+            Ok(version) => {
+              debug!("Found the version json at {path}, declared at {}", profile.json);
+              return Ok(version);
+            }
+            Err(err) => debug!("{path} isn't a version json: {err}"),
+          }
+        }
+        checked.extend(others);
+        Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("No version json in the installer, checked: {}", checked.join(", "))))
       }
     }
   }
@@ -74,7 +140,10 @@ pub struct ForgeVersionInfo {
   #[serde(rename = "type")]
   pub release_type: String,
 
+  // Not present in some legacy profiles
+  #[serde(default)]
   pub time: DateTime<Utc>,
+  #[serde(default)]
   pub release_time: DateTime<Utc>,
 
   #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -93,14 +162,122 @@ pub struct ForgeVersionInfo {
   pub arguments: HashMap<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl ForgeVersionInfo {
+  /// Game arguments of either format: the space separated `minecraftArguments` (1.12 and older) and the
+  /// plain strings of `arguments.game` (1.13+). Entries gated by rules are skipped for now.
+  pub fn get_game_arguments(&self) -> Vec<String> {
+    let mut arguments = self.minecraft_arguments.split_whitespace().map(str::to_string).collect::<Vec<_>>();
+    arguments.extend(self.get_plain_arguments("game"));
+    arguments
+  }
+
+  /// Plain strings of `arguments.jvm`, empty for the old format where the launcher supplies them.
+  /// Entries gated by rules are skipped for now.
+  pub fn get_jvm_arguments(&self) -> Vec<String> {
+    self.get_plain_arguments("jvm")
+  }
+
+  fn get_plain_arguments(&self, kind: &str) -> Vec<String> {
+    self.arguments
+      .get(kind)
+      .and_then(Value::as_array)
+      .into_iter()
+      .flatten()
+      .filter_map(Value::as_str)
+      .map(str::to_string)
+      .collect()
+  }
+
+  /// Merges this version into the `vanilla` json it inherits from, like the launcher does at launch time.
+  /// Forge's libraries come first and replace vanilla ones with the same group, name and classifier,
+  /// arguments are appended to the vanilla ones and the vanilla client jar is referenced through `jar`.
+  pub fn flatten(&self, vanilla: &Value) -> Result<Value, serde_json::Error> {
+    let Value::Object(forge) = serde_json::to_value(self)? else {
+      unreachable!("ForgeVersionInfo always serializes to an object");
+    };
+    let mut merged = vanilla.as_object().cloned().unwrap_or_default();
+    let vanilla_id = merged.get("id").cloned();
+    for (key, value) in forge {
+      match key.as_str() {
+        "inheritsFrom" => {}
+        "libraries" => {
+          let mut libraries = value.as_array().cloned().unwrap_or_default();
+          let replaced = libraries.iter().filter_map(get_library_key).collect::<Vec<_>>();
+          let vanilla_libraries = merged.get("libraries").and_then(Value::as_array).cloned().unwrap_or_default();
+          libraries.extend(vanilla_libraries.into_iter().filter(|lib| get_library_key(lib).is_none_or(|key| !replaced.contains(&key))));
+          merged.insert(key, Value::Array(libraries));
+        }
+        "arguments" => {
+          let mut arguments = merged.get("arguments").and_then(Value::as_object).cloned().unwrap_or_default();
+          for (kind, values) in value.as_object().cloned().unwrap_or_default() {
+            let mut all = arguments.get(&kind).and_then(Value::as_array).cloned().unwrap_or_default();
+            all.extend(values.as_array().cloned().unwrap_or_default());
+            arguments.insert(kind, Value::Array(all));
+          }
+          merged.insert(key, Value::Object(arguments));
+        }
+        _ => {
+          merged.insert(key, value);
+        }
+      }
+    }
+    if !merged.contains_key("jar") {
+      if let Some(id) = vanilla_id {
+        merged.insert("jar".to_string(), id);
+      }
+    }
+    Ok(Value::Object(merged))
+  }
+}
+
+/// Reads the profile and the version json of an installer jar downloaded into memory, without installing
+/// anything. V1 profiles embed their version json, V2 ones ship it next to the profile.
+pub fn fetch_version_json(installer_bytes: &[u8]) -> Result<(ForgeInstallerProfile, ForgeVersionInfo), ForgeInstallError> {
+  let archive = ZipArchive::new(Cursor::new(installer_bytes)).map_err(|err| forge_err!("Invalid installer archive: {err}"))?;
+  let mut source = ForgeInstallProfileSource::Zip(archive);
+  let profile = source.read_profile()?;
+  let version = profile.get_version_json(&mut source).map_err(|err| forge_err!("Failed to read the version json: {err}"))?;
+  Ok((profile, version))
+}
+
+/// "group:name:version[:classifier]" without the version, which is what a newer library replaces
+fn get_library_key(library: &Value) -> Option<String> {
+  let name = library["name"].as_str()?;
+  let mut parts = name.split(':').collect::<Vec<_>>();
+  if parts.len() > 2 {
+    parts.remove(2);
+  }
+  Some(parts.join(":"))
+}
+
+#[derive(Debug, Serialize, Clone)]
 #[serde(untagged)]
 pub enum ForgeVersionLibrary {
   Mojang(MojangLibrary),
   Forge(ForgeLibrary),
 }
 
+// Not untagged: an entry with `downloads` that fails to parse as a Mojang library would silently become a
+// Forge library without its downloads. The `downloads` key decides instead, and its errors are kept.
+impl<'de> Deserialize<'de> for ForgeVersionLibrary {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    if value.get("downloads").is_some() {
+      serde_json::from_value(value).map(Self::Mojang).map_err(de::Error::custom)
+    } else {
+      serde_json::from_value(value).map(Self::Forge).map_err(de::Error::custom)
+    }
+  }
+}
+
 impl ForgeVersionLibrary {
+  pub fn get_name(&self) -> &Artifact {
+    match self {
+      Self::Mojang(library) => &library.name,
+      Self::Forge(library) => &library.name,
+    }
+  }
+
   pub fn to_forge(&self) -> Option<&ForgeLibrary> {
     if let ForgeVersionLibrary::Forge(forge) = &self { Some(forge) } else { None }
   }
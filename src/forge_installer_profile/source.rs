@@ -0,0 +1,69 @@
+use std::{ fs::{ self, File }, io::{ self, ErrorKind, Read, Seek }, path::PathBuf };
+
+use zip::{ result::ZipError, ZipArchive };
+
+use crate::forge_client_install::ForgeInstallError;
+
+use super::ForgeInstallerProfile;
+
+/// Where the installer files (`install_profile.json`, the version json, `maven/` and `data/`) are read
+/// from: the installer jar itself or a folder it was extracted to, e.g. for a development build.
+pub enum ForgeInstallProfileSource<R: Read + Seek = File> {
+  Zip(ZipArchive<R>),
+  Directory(PathBuf),
+}
+
+impl<R: Read + Seek> ForgeInstallProfileSource<R> {
+  /// Opens the file at `name`, relative to the root of the installer. A missing file is reported as
+  /// [`ErrorKind::NotFound`].
+  pub fn by_name(&mut self, name: &str) -> io::Result<Box<dyn Read + '_>> {
+    match self {
+      Self::Zip(archive) =>
+        match archive.by_name(name) {
+          Ok(file) => Ok(Box::new(file)),
+          Err(ZipError::FileNotFound) => Err(io::Error::new(ErrorKind::NotFound, format!("{name} not found in installer archive"))),
+          Err(ZipError::Io(err)) => Err(err),
+          Err(err) => Err(io::Error::new(ErrorKind::InvalidData, err)),
+        }
+      Self::Directory(dir) => {
+        let path = dir.join(name.trim_start_matches('/'));
+        Ok(Box::new(File::open(path)?))
+      }
+    }
+  }
+
+  /// Paths of every file in the installer, relative to its root and separated by `/`
+  pub fn get_file_names(&self) -> io::Result<Vec<String>> {
+    match self {
+      Self::Zip(archive) => Ok(archive.file_names().filter(|name| !name.ends_with('/')).map(str::to_string).collect()),
+      Self::Directory(dir) => {
+        let mut names = vec![];
+        let mut pending = vec![dir.clone()];
+        while let Some(current) = pending.pop() {
+          for entry in fs::read_dir(current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+              pending.push(path);
+            } else if let Ok(relative) = path.strip_prefix(dir) {
+              names.push(relative.components().map(|part| part.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/"));
+            }
+          }
+        }
+        Ok(names)
+      }
+    }
+  }
+
+  /// Reads and parses `install_profile.json`
+  pub fn read_profile(&mut self) -> Result<ForgeInstallerProfile, ForgeInstallError> {
+    let location = match self {
+      Self::Zip(_) => "<memory>".to_string(),
+      Self::Directory(dir) => dir.display().to_string(),
+    };
+    match self.by_name("install_profile.json") {
+      Ok(reader) => ForgeInstallerProfile::from_reader(reader),
+      Err(err) if err.kind() == ErrorKind::NotFound => Err(ForgeInstallError::ProfileNotFound(location)),
+      Err(err) => Err(ForgeInstallError::InvalidProfile(err.to_string())),
+    }
+  }
+}
@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+/// Resolves a processor argument (or output) the way the Forge installer does:
+///
+/// - `{KEY}` is replaced by the value of `KEY` in `tokens`. A key that's present with an empty value
+///   resolves to an empty string, a missing key is an error.
+/// - `'text'` is copied as is, without the quotes and without looking anything up.
+/// - `\` escapes the next character, both outside and inside braces and quotes, so `\{` is a literal `{`.
+///
+/// A trailing `\` and an unclosed `{` or `'` are errors.
+pub fn replace_tokens(tokens: &HashMap<String, String>, value: &str) -> Result<String, String> {
+  let mut buf = String::new();
+  let mut chars = value.chars();
+  let bad_escape = || format!("Illegal pattern (Bad escape): {}", value);
+
+  while let Some(ch) = chars.next() {
+    match ch {
+      '\\' => buf.push(chars.next().ok_or_else(bad_escape)?),
+      '{' | '\'' => {
+        let close = if ch == '{' { '}' } else { '\'' };
+        let mut key = String::new();
+        loop {
+          match chars.next() {
+            None => {
+              return Err(format!("Illegal pattern (Unclosed {}): {}", ch, value));
+            }
+            Some('\\') => key.push(chars.next().ok_or_else(bad_escape)?),
+            Some(d) if d == close => break,
+            Some(d) => key.push(d),
+          }
+        }
+
+        if ch == '\'' {
+          buf.push_str(&key);
+        } else {
+          // A present but empty value is intentional (data only used by the other side)
+          match tokens.get(&key) {
+            Some(token) => buf.push_str(token),
+            None => {
+              return Err(format!("Illegal pattern: {} Missing Key: {}", value, key));
+            }
+          }
+        }
+      }
+      _ => buf.push(ch),
+    }
+  }
+
+  Ok(buf)
+}
@@ -1,6 +1,6 @@
-use std::{ fmt::Debug, fs::{ create_dir_all, File }, path::PathBuf };
+use std::{ fmt::Debug, fs::{ create_dir_all, File }, path::Path };
 
-use crate::{ Artifact, Sha1Sum };
+use crate::{ Artifact, Repositories, Sha1Sum };
 use super::{ ForgeVersionInfo, ForgeVersionLibrary };
 use log::info;
 use serde::{ Deserialize, Serialize };
@@ -16,7 +16,7 @@ pub struct ForgeInstallerProfileV1 {
 }
 
 impl ForgeInstallerProfileV1 {
-  pub fn get_libraries(&self, marker: &str, filter: fn(&str) -> bool) -> Vec<ForgeLibrary> {
+  pub fn get_libraries(&self, marker: &str, filter: impl Fn(&str) -> bool) -> Vec<ForgeLibrary> {
     let mut ret = vec![];
     self.version_info.libraries
       .iter()
@@ -25,13 +25,21 @@ impl ForgeInstallerProfileV1 {
       .for_each(|lib| ret.push(lib.clone()));
 
     for opt in &self.optionals {
-      let mut info = ForgeLibrary::new(&opt, marker);
+      let mut info = ForgeLibrary::new(opt, marker);
       info.enabled = filter(&opt.artifact.get_descriptor());
       ret.push(info);
     }
     ret
   }
 
+  /// Optional mods a user can opt into for `side` ("client" or "server")
+  pub fn get_optionals(&self, side: &str) -> Vec<&ForgeOptional> {
+    self.optionals
+      .iter()
+      .filter(|opt| opt.is_side(side))
+      .collect()
+  }
+
   pub fn is_inherited_json(&self) -> bool {
     self.version_info.inherits_from.is_some() && self.version_info.jar.is_some()
   }
@@ -78,11 +86,16 @@ impl ForgeLibrary {
   }
 
   pub fn get_url(&self) -> String {
+    self.get_url_or(&Repositories::default().libraries_base)
+  }
+
+  /// Like [`Self::get_url`], with `libraries_base` used for libraries without their own url
+  pub fn get_url_or(&self, libraries_base: &str) -> String {
     if let Some(url) = &self.url {
       // If it has mirrors, return mirror url (so self.url is ignored, idk why)
       format!("{url}/")
     } else {
-      return "https://libraries.minecraft.net/".to_string();
+      return libraries_base.to_string();
     }
   }
 
@@ -110,11 +123,15 @@ pub struct ForgeOptional {
 }
 
 impl ForgeOptional {
+  pub fn is_side(&self, side: &str) -> bool {
+    if side == "client" { self.client } else { self.server }
+  }
+
   pub fn save_mod_list_json(
-    root: &PathBuf,
-    json: &PathBuf,
+    root: &Path,
+    json: &Path,
     libs: &Vec<ForgeOptional>,
-    filter: fn(&str) -> bool
+    filter: impl Fn(&str) -> bool
   ) -> Result<(), Box<dyn std::error::Error>> {
     let mut artifacts = vec![];
     for lib in libs {
@@ -140,16 +157,23 @@ impl ForgeOptional {
   }
 }
 
+// Legacy installers (1.5.2 and older 1.6 builds) leave out some of these, so only the fields the
+// install actually needs are required
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct InstallSectionV1 {
+  #[serde(default, skip_serializing_if = "String::is_empty")]
   pub profile_name: String,
   pub target: String,
   pub path: Artifact,
+  #[serde(default, skip_serializing_if = "String::is_empty")]
   pub version: String,
+  #[serde(default, skip_serializing_if = "String::is_empty")]
   pub file_path: String,
+  #[serde(default, skip_serializing_if = "String::is_empty")]
   pub welcome: String,
   pub minecraft: String,
+  #[serde(default, skip_serializing_if = "String::is_empty")]
   pub logo: String,
   #[serde(default, skip_serializing_if = "Option::is_none")]
   pub mirror_list: Option<String>,
@@ -158,3 +182,15 @@ pub struct InstallSectionV1 {
   #[serde(default, skip_serializing_if = "Option::is_none")]
   pub strip_meta: Option<bool>,
 }
+
+impl InstallSectionV1 {
+  /// Path of the universal jar inside the installer. Falls back to the name legacy installers used
+  /// when `filePath` is missing.
+  pub fn get_file_path(&self) -> String {
+    if self.file_path.is_empty() {
+      format!("{}-universal-{}-{}.jar", self.path.artifact_id, self.minecraft, self.path.version)
+    } else {
+      self.file_path.clone()
+    }
+  }
+}
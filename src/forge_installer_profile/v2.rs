@@ -1,19 +1,23 @@
 use std::{
   collections::HashMap,
+  fmt::Debug,
   path::{ PathBuf, Path },
-  io::{ ErrorKind, Read, BufReader, BufRead, Cursor },
+  sync::Arc,
+  io::{ ErrorKind, Read },
   fs::{ File, self },
-  process::{ Command, Stdio }, os::windows::process::CommandExt,
+  process::{ Child, Command, Output, Stdio }, os::windows::process::CommandExt,
+  thread,
+  time::Duration,
 };
 
 use chrono::{ DateTime, Utc };
-use log::{ info, error };
+use log::{ debug, info, error, warn };
 use zip::ZipArchive;
-use crate::{ Sha1Sum, Artifact };
+use crate::{ Sha1Sum, Artifact, forge_client_install::{ CancellationToken, ForgeInstallError } };
 use serde::{ Deserialize, Serialize };
 use serde_json::Value;
 
-use super::ForgeVersionLibrary;
+use super::{ tokens::replace_tokens, ForgeVersionLibrary };
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -64,6 +68,16 @@ impl ForgeInstallerProfileV2 {
   pub fn get_libraries(&self) -> Vec<&ForgeVersionLibrary> {
     self.libraries.iter().collect()
   }
+
+  /// Known size in bytes of the profile libraries, which include every processor jar and its classpath.
+  /// Libraries without a size count as zero.
+  pub fn get_total_download_size(&self) -> u64 {
+    self.libraries
+      .iter()
+      .filter_map(ForgeVersionLibrary::to_mojang)
+      .filter_map(MojangLibrary::get_size)
+      .sum()
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -72,6 +86,72 @@ pub struct DataFile {
   server: String,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ProcessorOutput {
+  /// False when every output was already cached and java wasn't run
+  pub ran: bool,
+  pub stdout: String,
+  pub stderr: String,
+  /// Outputs declared by the processor whose checksum was validated, also filled on a cache hit
+  pub outputs: HashMap<PathBuf, Sha1Sum>,
+}
+
+/// A processor about to be run by java, given to the [`ProcessorHook`]
+#[derive(Debug, Clone)]
+pub struct ProcessorInvocation {
+  pub jar: Artifact,
+  pub main_class: String,
+  /// Arguments of the processor itself, with the tokens resolved
+  pub args: Vec<String>,
+  /// Full command line, the java binary first
+  pub command: Vec<String>,
+}
+
+/// What to do with a processor, see [`ProcessorHook`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessorDecision {
+  Run,
+  /// Not run, its outputs aren't validated either
+  Skip,
+  /// Fails the install with [`ForgeInstallError::ProcessorRejected`]
+  Abort,
+}
+
+/// Called before java runs a processor (not for cached ones), deciding whether it runs
+#[derive(Clone)]
+pub struct ProcessorHook(pub Arc<dyn Fn(&ProcessorInvocation) -> ProcessorDecision + Send + Sync>);
+
+impl ProcessorHook {
+  pub fn new(hook: impl Fn(&ProcessorInvocation) -> ProcessorDecision + Send + Sync + 'static) -> Self {
+    Self(Arc::new(hook))
+  }
+}
+
+impl Debug for ProcessorHook {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "ProcessorHook")
+  }
+}
+
+/// Extra options for the java running the processors, e.g. a bigger heap for large installs
+#[derive(Debug, Clone, Default)]
+pub struct JvmOptions {
+  /// Passed before the classpath, like `-Xmx2g` or `--add-opens`
+  pub args: Vec<String>,
+  /// Set as the `JAVA_TOOL_OPTIONS` environment variable of the processors
+  pub java_tool_options: Option<String>,
+}
+
+/// How [`Processor::process`] runs java: the binary and its options, the platform the classpath is built for
+/// and the hook called before each processor
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessorEnv<'a> {
+  pub java_path: &'a PathBuf,
+  pub jvm_options: &'a JvmOptions,
+  pub os: &'a TargetOs,
+  pub on_before: Option<&'a ProcessorHook>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Processor {
   #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -90,30 +170,37 @@ impl Processor {
     if let Some(sides) = &self.sides { sides.contains(&side.to_string()) } else { true }
   }
 
-  pub fn process(&self, data: &HashMap<String, String>, libraries_dir: &PathBuf, java_path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+  pub fn process(
+    &self,
+    data: &HashMap<String, String>,
+    libraries_dir: &Path,
+    env: &ProcessorEnv,
+    cancel: &CancellationToken
+  ) -> Result<ProcessorOutput, Box<dyn std::error::Error>> {
     let mut outputs = HashMap::new();
+    let mut validated = HashMap::new();
     if !&self.outputs.is_empty() {
       let mut miss = false;
       info!("  Cache: ");
       for (e_key, e_value) in &self.outputs.clone() {
         let key = if e_key.starts_with('[') && e_key.ends_with(']') {
           let artifact = Artifact::try_from(e_key[1..e_key.len() - 1].to_string())?;
-          Some(artifact.get_local_path(&libraries_dir).to_str().unwrap().to_string())
+          artifact.get_local_path(libraries_dir).to_str().unwrap().to_string()
         } else {
-          Some(replace_tokens(data, &e_key)?)
+          replace_tokens(data, e_key)?
+        };
+        let value = match e_value {
+          Some(e_value) =>
+            Some(
+              replace_tokens(data, e_value).map_err(|_|
+                std::io::Error::new(ErrorKind::Other, format!("Invalid configuration, bad output config: [{key}: {e_value}]"))
+              )?
+            ),
+          None => {
+            warn!("    {key} has no checksum, only checking that it exists");
+            None
+          }
         };
-        let mut value = e_value.clone();
-        if let Some(value1) = value {
-          value = replace_tokens(data, &value1).ok();
-        }
-        if key.is_none() || value.is_none() {
-          return Err(
-            Box::new(
-              std::io::Error::new(ErrorKind::Other, format!("Invalid configuration, bad output config: [{}: {}]", key.unwrap(), value.unwrap()))
-            )
-          );
-        }
-        let (key, value) = (key.unwrap(), value.unwrap());
         outputs.insert(key.clone(), value.clone());
         let artifact = Path::new(&key);
         if !artifact.exists() {
@@ -121,72 +208,54 @@ impl Processor {
           miss = true;
           continue;
         }
-        let sha = Sha1Sum::from_reader(&mut File::open(artifact)?).ok();
-        if sha == Sha1Sum::try_from(value.clone()).ok() {
+        let Some(value) = value else {
+          info!("    {key} Exists");
+          continue;
+        };
+        let sha = Sha1Sum::from_file(artifact).ok();
+        if let Some(expected) = Sha1Sum::try_from(value.clone()).ok().filter(|expected| sha.as_ref() == Some(expected)) {
           info!("    {key} Validated: {value}");
+          validated.insert(artifact.to_path_buf(), expected);
           continue;
         }
         info!("    {key}");
         info!("      Expected: {}", value);
-        info!("      Actual:   {}", sha.unwrap());
+        if let Some(sha) = &sha {
+          info!("      Actual:   {sha}");
+        }
         miss = true;
         fs::remove_file(artifact)?;
       }
       if !miss {
         info!("  Cache Hit!");
         // continue;
-        return Ok(());
+        return Ok(ProcessorOutput { outputs: validated, ..ProcessorOutput::default() });
       }
     }
-    let jar = &self.jar.get_local_path(&libraries_dir);
+    let jar = &self.jar.get_local_path(libraries_dir);
     if !jar.exists() || !jar.is_file() {
       return Err(Box::new(std::io::Error::new(ErrorKind::Other, format!("  Missing Jar for processor: {}", jar.display()))));
     }
 
-    let main_class = {
-      let mut buf = String::new();
-      let mut jar_file = ZipArchive::new(File::open(&jar)?)?;
-      jar_file.by_name("META-INF/MANIFEST.MF")?.read_to_string(&mut buf)?;
-      buf
-        .lines()
-        .filter_map(|line| line.split_once(":"))
-        .find(|(key, _)| key == &"Main-Class")
-        .map(|(_, value)| value.trim())
-        .unwrap_or_default()
-        .to_string()
-    };
+    let main_class = read_main_class(jar)?;
     if main_class.is_empty() {
       return Err(Box::new(std::io::Error::new(ErrorKind::Other, format!("  Jar does not have main class: {}", jar.to_str().unwrap()))));
     }
     info!("  MainClass: {main_class}");
-    let mut classpath = vec![];
     let mut err = String::new();
     info!("  Classpath:");
     info!("    {}", jar.to_str().unwrap());
-    classpath.push(jar.clone());
     for dep in &self.classpath {
-      let lib = dep.get_local_path(&libraries_dir);
+      let lib = dep.get_local_path(libraries_dir);
       if !lib.is_file() {
         err.push_str(&format!("\n  {}", dep.get_descriptor()));
       }
       info!("    {}", lib.to_str().unwrap());
-      classpath.push(lib);
     }
     if err.len() > 0 {
       return Err(Box::new(std::io::Error::new(ErrorKind::Other, format!("  Missing Processor Dependencies: {err}"))));
     }
-    let mut args = vec![];
-    for arg in &self.args {
-      if arg.starts_with('[') && arg.ends_with(']') {
-        let artifact = Artifact::try_from(arg[1..arg.len() - 1].to_string())?;
-        args.push(artifact.get_local_path(&libraries_dir).to_str().unwrap().to_string());
-      } else {
-        args.push(replace_tokens(&data, &arg)?);
-      }
-    }
-    if err.len() > 0 {
-      return Err(Box::new(std::io::Error::new(ErrorKind::Other, format!("  Missing Processor data values: {err}"))));
-    }
+    let args = self.get_args(data, libraries_dir)?;
     info!(
       "  Args: {}",
       args
@@ -195,47 +264,73 @@ impl Processor {
         .collect::<Vec<String>>()
         .join(", ")
     );
-
-    let classpath_separator = if cfg!(windows) { ";" } else { ":" };
-    let mut cmd_args = vec!["-cp".to_string()];
-    let classpath = classpath
-      .iter()
-      .map(|path| path.to_str().unwrap())
-      .collect::<Vec<_>>()
-      .join(classpath_separator);
-    cmd_args.push(classpath);
-    cmd_args.push(main_class);
-    cmd_args.extend(args);
-
-    {
-      let child = Command::new(java_path.to_str().unwrap()).stdout(Stdio::piped()).stderr(Stdio::piped()).args(cmd_args).creation_flags(0x08000000).spawn()?.wait_with_output()?;
-      let stdout = BufReader::new(Cursor::new(child.stdout));
-      let stderr = BufReader::new(Cursor::new(child.stderr));
-      for line in stdout.lines() {
-        if let Ok(line) = line {
-          info!("{line}");
-        }
-      }
-      for line in stderr.lines() {
-        if let Ok(line) = line {
-          error!("{line}");
+    let mut cmd_args = env.jvm_options.args.clone();
+    cmd_args.extend(self.get_java_args(&main_class, data, libraries_dir, env.os)?);
+    debug!("  Command: {}", format_command(env.java_path.to_str().unwrap(), &cmd_args));
+    if let Some(on_before) = env.on_before {
+      let mut command = vec![env.java_path.to_str().unwrap().to_string()];
+      command.extend(cmd_args.iter().cloned());
+      let invocation = ProcessorInvocation { jar: self.jar.clone(), main_class: main_class.clone(), args, command };
+      match (on_before.0)(&invocation) {
+        ProcessorDecision::Run => {}
+        ProcessorDecision::Skip => {
+          warn!("  Skipped processor {}", self.jar);
+          return Ok(ProcessorOutput::default());
         }
+        ProcessorDecision::Abort => Err(ForgeInstallError::ProcessorRejected(self.jar.to_string()))?,
       }
     }
 
+    let mut output = {
+      let mut command = Command::new(env.java_path.to_str().unwrap());
+      if let Some(java_tool_options) = &env.jvm_options.java_tool_options {
+        command.env("JAVA_TOOL_OPTIONS", java_tool_options);
+      }
+      let child = command.stdout(Stdio::piped()).stderr(Stdio::piped()).args(cmd_args).creation_flags(0x08000000).spawn()?;
+      let child = wait_with_output(child, cancel)?;
+      let output = ProcessorOutput {
+        ran: true,
+        stdout: String::from_utf8_lossy(&child.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&child.stderr).to_string(),
+        outputs: HashMap::new(),
+      };
+      for line in output.stdout.lines() {
+        info!("{line}");
+      }
+      for line in output.stderr.lines() {
+        error!("{line}");
+      }
+      if !child.status.success() {
+        return Err(
+          Box::new(ForgeInstallError::ProcessorFailed {
+            jar: self.jar.to_string(),
+            code: child.status.code(),
+            stderr: output.stderr,
+          })
+        );
+      }
+      output
+    };
+
     for (key, value) in outputs {
       let artifact = Path::new(&key);
       if !artifact.exists() {
         err.push_str(&format!("\n    {key} missing"));
         continue;
       }
-      let sha = Sha1Sum::from_reader(&mut File::open(artifact)?)?;
-      if sha == Sha1Sum::try_from(value.clone())? {
-        info!("  Output: {key} Checksum Validated: {sha}");
+      let Some(value) = value else {
+        info!("  Output: {key} Exists, no checksum to validate");
+        continue;
+      };
+      let expected = Sha1Sum::try_from(value.clone())?;
+      let sha = Sha1Sum::from_file(artifact)?;
+      if sha == expected {
+        info!("  Output: {key} Checksum Validated: {value}");
+        output.outputs.insert(artifact.to_path_buf(), expected);
         continue;
       }
-      err.push_str(&format!("\n    {key}\n      Expected: {value}\n      Actual:  {sha}"));
-      if fs::remove_file(&artifact).is_err() {
+      err.push_str(&format!("\n    {key}\n      Expected: {value}\n      Actual:   {sha}"));
+      if fs::remove_file(artifact).is_err() {
         err.push_str(&format!("\n      Could not delete file"));
       }
     }
@@ -243,8 +338,119 @@ impl Processor {
       return Err(Box::new(std::io::Error::new(ErrorKind::Other, format!("  Processor failed, invalid outputs: {err}"))));
     }
 
-    Ok(())
+    Ok(output)
   }
+
+  /// The processor jar followed by its classpath, in the order passed to java
+  pub fn get_classpath(&self, libraries_dir: &Path) -> Vec<PathBuf> {
+    [&self.jar]
+      .into_iter()
+      .chain(&self.classpath)
+      .map(|artifact| artifact.get_local_path(libraries_dir))
+      .collect()
+  }
+
+  /// Resolves the arguments: `[artifact]` becomes its path in `libraries_dir`, everything else goes
+  /// through [`replace_tokens`] with `data`
+  pub fn get_args(&self, data: &HashMap<String, String>, libraries_dir: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut args = vec![];
+    for arg in &self.args {
+      if arg.starts_with('[') && arg.ends_with(']') {
+        let artifact = Artifact::try_from(arg[1..arg.len() - 1].to_string())?;
+        args.push(artifact.get_local_path(libraries_dir).to_str().unwrap().to_string());
+      } else {
+        args.push(replace_tokens(data, arg)?);
+      }
+    }
+    Ok(args)
+  }
+
+  /// Arguments java is run with: the classpath, `main_class` and the resolved processor arguments. The
+  /// classpath is joined with the separator of `os`, which doesn't have to be the host's.
+  pub fn get_java_args(
+    &self,
+    main_class: &str,
+    data: &HashMap<String, String>,
+    libraries_dir: &Path,
+    os: &TargetOs
+  ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let classpath = self
+      .get_classpath(libraries_dir)
+      .iter()
+      .map(|path| path.to_str().unwrap().to_string())
+      .collect::<Vec<_>>()
+      .join(os.get_classpath_separator());
+    let mut cmd_args = vec!["-cp".to_string(), classpath, main_class.to_string()];
+    cmd_args.extend(self.get_args(data, libraries_dir)?);
+    Ok(cmd_args)
+  }
+}
+
+/// Joins a command line so it can be pasted in a shell, arguments that are empty or contain whitespace or
+/// quotes are double quoted
+pub fn format_command(program: &str, args: &[String]) -> String {
+  let quote = |arg: &str| {
+    if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+      format!("\"{}\"", arg.replace('"', "\\\""))
+    } else {
+      arg.to_string()
+    }
+  };
+  [program]
+    .into_iter()
+    .chain(args.iter().map(String::as_str))
+    .map(quote)
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Reads the `Main-Class` from the manifest of `jar`, empty when it has none
+pub fn read_main_class(jar: &Path) -> Result<String, Box<dyn std::error::Error>> {
+  let mut buf = String::new();
+  let mut jar_file = ZipArchive::new(File::open(jar)?)?;
+  jar_file.by_name("META-INF/MANIFEST.MF")?.read_to_string(&mut buf)?;
+  Ok(
+    buf
+      .lines()
+      .filter_map(|line| line.split_once(":"))
+      .find(|(key, _)| key == &"Main-Class")
+      .map(|(_, value)| value.trim())
+      .unwrap_or_default()
+      .to_string()
+  )
+}
+
+/// Like [`Child::wait_with_output`], but kills the child as soon as `cancel` is cancelled
+fn wait_with_output(mut child: Child, cancel: &CancellationToken) -> Result<Output, Box<dyn std::error::Error>> {
+  // Drain the pipes on their own threads so the child can't block on a full pipe while we poll it
+  let stdout = child.stdout.take().map(|mut pipe|
+    thread::spawn(move || {
+      let mut buf = vec![];
+      let _ = pipe.read_to_end(&mut buf);
+      buf
+    })
+  );
+  let stderr = child.stderr.take().map(|mut pipe|
+    thread::spawn(move || {
+      let mut buf = vec![];
+      let _ = pipe.read_to_end(&mut buf);
+      buf
+    })
+  );
+  let status = loop {
+    if cancel.is_cancelled() {
+      let _ = child.kill();
+      let _ = child.wait();
+      Err(ForgeInstallError::Cancelled)?;
+    }
+    if let Some(status) = child.try_wait()? {
+      break status;
+    }
+    thread::sleep(Duration::from_millis(50));
+  };
+  let stdout = stdout.map(|handle| handle.join().unwrap_or_default()).unwrap_or_default();
+  let stderr = stderr.map(|handle| handle.join().unwrap_or_default()).unwrap_or_default();
+  Ok(Output { status, stdout, stderr })
 }
 
 // Move to mod
@@ -254,7 +460,178 @@ pub struct MojangLibrary {
   // extract: Option<MojangLibraryExtractRules>,
   pub name: Artifact,
   pub downloads: /*Option<*/ MojangLibraryDownloads /* >*/,
-  // natives:
+  /// Repository of a Forge-style entry that also has `downloads`, kept so the json round trips
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub url: Option<String>,
+  // [os]: classifier, e.g. "windows": "natives-windows-${arch}"
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub natives: Option<HashMap<String, String>>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub rules: Option<Vec<Rule>>,
+}
+
+impl MojangLibrary {
+  /// Size in bytes of the main artifact, natives not included
+  pub fn get_size(&self) -> Option<u64> {
+    self.downloads.artifact.as_ref()?.size
+  }
+
+  /// Applies the rules like the launcher does: without rules a library is always allowed, otherwise
+  /// it's disallowed unless a matching rule allows it, the last matching rule wins.
+  pub fn is_allowed(&self, os: &TargetOs, arch: &TargetArch) -> bool {
+    let Some(rules) = &self.rules else {
+      return true;
+    };
+    let mut allowed = false;
+    for rule in rules.iter().filter(|rule| rule.matches(os, arch)) {
+      allowed = rule.action == RuleAction::Allow;
+    }
+    allowed
+  }
+
+  pub fn get_native_classifier(&self, os: &TargetOs, arch: &TargetArch) -> Option<String> {
+    match &self.natives {
+      Some(natives) => natives.get(os.get_name()).map(|classifier| classifier.replace("${arch}", arch.get_bits())),
+      None => Some(format!("natives-{}", os.get_name())),
+    }
+  }
+
+  /// Returns the native artifact for the given os, if this library has one
+  pub fn get_native(&self, os: &TargetOs, arch: &TargetArch) -> Option<(Artifact, MojangArtifact)> {
+    let classifier = self.get_native_classifier(os, arch)?;
+    let download = self.downloads.classifiers.as_ref()?.get(&classifier)?;
+    let name = &self.name;
+    let descriptor = format!("{}:{}:{}:{classifier}@{}", name.group_id.join("."), name.artifact_id, name.version, name.ext);
+    let artifact = Artifact::try_from(descriptor).ok()?;
+    Some((artifact, download.clone()))
+  }
+
+  /// Every artifact of this library that has to be on disk for the given os: the main artifact and the
+  /// native classifier, if any. Libraries that only ship natives don't have a main artifact.
+  pub fn get_artifacts(&self, os: &TargetOs, arch: &TargetArch) -> Vec<(Artifact, MojangArtifact)> {
+    let mut artifacts = vec![];
+    let native = self.get_native(os, arch);
+    if self.downloads.artifact.is_some() || native.is_none() {
+      let download = self.downloads.artifact.as_ref().cloned().unwrap_or(MojangArtifact::new(self.name.get_path_string()));
+      artifacts.push((self.name.clone(), download));
+    }
+    artifacts.extend(native);
+    artifacts
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Rule {
+  pub action: RuleAction,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub os: Option<OsRule>,
+  // Launcher features (demo user, custom resolution...), never enabled by the installer
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub features: Option<HashMap<String, bool>>,
+}
+
+impl Rule {
+  pub fn matches(&self, os: &TargetOs, arch: &TargetArch) -> bool {
+    if self.features.as_ref().is_some_and(|features| !features.is_empty()) {
+      return false;
+    }
+    match &self.os {
+      Some(rule) => rule.name.as_ref().is_none_or(|name| name == os.get_name()) && rule.arch.as_ref().is_none_or(|rule_arch| rule_arch == arch.get_name()),
+      None => true,
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+  Allow,
+  Disallow,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OsRule {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub arch: Option<String>,
+  // Regex on the os version, not checked
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub version: Option<String>,
+}
+
+/// Arch name used by the launcher manifests
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetOs {
+  Windows,
+  Linux,
+  Osx,
+}
+
+impl TargetOs {
+  pub fn current() -> Self {
+    match std::env::consts::OS {
+      "macos" => Self::Osx,
+      "linux" => Self::Linux,
+      _ => Self::Windows,
+    }
+  }
+
+  /// Name used by the launcher manifests
+  pub fn get_name(&self) -> &'static str {
+    match self {
+      Self::Windows => "windows",
+      Self::Linux => "linux",
+      Self::Osx => "osx",
+    }
+  }
+
+  /// Separator of the entries of a java `-cp`
+  pub fn get_classpath_separator(&self) -> &'static str {
+    if *self == Self::Windows { ";" } else { ":" }
+  }
+
+  pub fn get_path_separator(&self) -> &'static str {
+    if *self == Self::Windows { "\\" } else { "/" }
+  }
+}
+
+/// Architecture the library rules and the `${arch}` of the natives are resolved for, next to [`TargetOs`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetArch {
+  X86,
+  X86_64,
+  Arm,
+  Arm64,
+}
+
+impl TargetArch {
+  pub fn current() -> Self {
+    match std::env::consts::ARCH {
+      "x86" => Self::X86,
+      "arm" => Self::Arm,
+      "aarch64" => Self::Arm64,
+      _ => Self::X86_64,
+    }
+  }
+
+  /// Name used by the rules of the launcher manifests
+  pub fn get_name(&self) -> &'static str {
+    match self {
+      Self::X86 => "x86",
+      Self::X86_64 => "x86_64",
+      Self::Arm => "arm",
+      Self::Arm64 => "arm64",
+    }
+  }
+
+  /// Replaces `${arch}` in the native classifiers
+  pub fn get_bits(&self) -> &'static str {
+    match self {
+      Self::X86 | Self::Arm => "32",
+      Self::X86_64 | Self::Arm64 => "64",
+    }
+  }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -274,7 +651,7 @@ pub struct MojangArtifact {
   #[serde(default, skip_serializing_if = "Option::is_none")]
   pub sha1: Option</*String*/ Sha1Sum>,
   #[serde(default, skip_serializing_if = "Option::is_none")]
-  pub size: Option<u32>,
+  pub size: Option<u64>,
 }
 
 impl MojangArtifact {
@@ -314,7 +691,7 @@ pub struct ForgeVersionFileV2 {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Download {
   pub sha1: Sha1Sum /*String*/,
-  pub size: u16,
+  pub size: u64,
   #[serde(default, skip_serializing_if = "Option::is_none")]
   pub url: Option<String>,
   #[serde(default)]
@@ -326,69 +703,3 @@ impl Download {
     if self.url.is_none() || self.provided { Some(String::new()) } else { self.url.clone() }
   }
 }
-
-fn replace_tokens(tokens: &HashMap<String, String>, value: &str) -> Result<String, String> {
-  let mut buf = String::new();
-  let mut char_index = 0;
-
-  while char_index < value.len() {
-    let ch = value.chars().nth(char_index).unwrap();
-    if ch == '\\' {
-      if char_index == value.len() - 1 {
-        return Err(format!("Illegal pattern (Bad escape): {}", value));
-      }
-      buf.push(
-        value
-          .chars()
-          .nth(char_index + 1)
-          .unwrap()
-      );
-      char_index += 2;
-    } else if ch == '{' || ch == '\'' {
-      let mut key = String::new();
-      let mut y = char_index + 1;
-
-      while y <= value.len() {
-        if y == value.len() {
-          return Err(format!("Illegal pattern (Unclosed {}): {}", ch, value));
-        }
-        let d = value.chars().nth(y).unwrap();
-
-        if d == '\\' {
-          if y == value.len() - 1 {
-            return Err(format!("Illegal pattern (Bad escape): {}", value));
-          }
-          key.push(
-            value
-              .chars()
-              .nth(y + 1)
-              .unwrap()
-          );
-          y += 2;
-        } else {
-          if (ch == '{' && d == '}') || (ch == '\'' && d == '\'') {
-            char_index = y;
-            break;
-          }
-          key.push(d);
-          y += 1;
-        }
-      }
-
-      if ch == '\'' {
-        buf.push_str(&key);
-      } else {
-        if !tokens.contains_key(&key) {
-          return Err(format!("Illegal pattern: {} Missing Key: {}", value, key));
-        }
-        buf.push_str(tokens.get(&key).unwrap());
-      }
-      char_index += 1;
-    } else {
-      buf.push(ch);
-      char_index += 1;
-    }
-  }
-
-  Ok(buf)
-}
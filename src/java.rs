@@ -0,0 +1,36 @@
+use std::{ os::windows::process::CommandExt, path::Path, process::Command };
+
+use crate::forge_client_install::ForgeInstallError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JavaVersion {
+  /// Full version string, as printed by `java -version` (e.g. `17.0.6` or `1.8.0_351`)
+  pub version: String,
+  pub major: u32,
+}
+
+impl JavaVersion {
+  /// Parses the output of `java -version`, which looks like `openjdk version "17.0.6" 2023-01-17`.
+  pub fn parse(output: &str) -> Option<Self> {
+    let line = output.lines().find(|line| line.contains(" version "))?;
+    let version = line.split('"').nth(1)?.to_string();
+    let mut parts = version.split(|c: char| !c.is_ascii_digit());
+    let mut major = parts.next()?.parse().ok()?;
+    // Java 8 and older report themselves as 1.x
+    if major == 1 {
+      major = parts.next()?.parse().ok()?;
+    }
+    Some(Self { version, major })
+  }
+}
+
+pub fn verify_java(java_path: &Path) -> Result<JavaVersion, ForgeInstallError> {
+  let output = Command::new(java_path)
+    .arg("-version")
+    .creation_flags(0x08000000)
+    .output()
+    .map_err(|err| forge_err!("Couldn't run java at {}: {err}", java_path.display()))?;
+  // java -version prints to stderr
+  let stderr = String::from_utf8_lossy(&output.stderr);
+  JavaVersion::parse(&stderr).ok_or_else(|| forge_err!("Couldn't parse java version from {}: {stderr}", java_path.display()))
+}
@@ -3,14 +3,19 @@ pub mod forge_client_install;
 pub mod forge_installer_profile;
 pub mod post_processors;
 pub mod download_utils;
+pub mod java;
+pub mod prelude;
 
-use std::{ fmt::{ Debug, Display }, fs, io::Read, path::PathBuf };
+use std::{ env, fmt::{ Debug, Display }, fs, hash::{ Hash, Hasher }, io::{ self, ErrorKind, Read }, path::{ Path, PathBuf }, str::FromStr, time::Duration };
 
 use chrono::{ DateTime, Utc };
-use reqwest::Client;
+use log::warn;
+use reqwest::header::HeaderMap;
 use serde::{ Deserialize, Serialize };
 use serde_json::Value;
 use sha1::{ Digest, Sha1 };
+use sha2::Sha256;
+use thiserror::Error;
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(try_from = "String", into = "String")]
@@ -35,6 +40,29 @@ impl Display for Artifact {
   }
 }
 
+// Equality ignores how the artifact was written, only its coordinates matter
+impl PartialEq for Artifact {
+  fn eq(&self, other: &Self) -> bool {
+    self.group_id == other.group_id &&
+      self.artifact_id == other.artifact_id &&
+      self.version == other.version &&
+      self.classifier == other.classifier &&
+      self.ext == other.ext
+  }
+}
+
+impl Eq for Artifact {}
+
+impl Hash for Artifact {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.group_id.hash(state);
+    self.artifact_id.hash(state);
+    self.version.hash(state);
+    self.classifier.hash(state);
+    self.ext.hash(state);
+  }
+}
+
 impl Artifact {
   pub fn get_file(&self) -> String {
     let mut name = format!("{}-{}", self.artifact_id, self.version);
@@ -57,8 +85,8 @@ impl Artifact {
     self.get_path_vec().join("/")
   }
 
-  pub fn get_local_path(&self, root: &PathBuf) -> PathBuf {
-    let mut root = root.clone();
+  pub fn get_local_path(&self, root: &Path) -> PathBuf {
+    let mut root = root.to_path_buf();
     for s in self.get_path_vec() {
       root = root.join(s);
     }
@@ -73,20 +101,98 @@ impl Artifact {
       if let Some(classifier) = &self.classifier {
         descriptor.push_str(&format!(":{}", classifier));
       }
+      if self.ext != "jar" {
+        descriptor.push_str(&format!("@{}", self.ext));
+      }
       descriptor
     }
   }
+
+  /// Parses a repository path like `net/minecraftforge/forge/1.20.1-47.2.0/forge-1.20.1-47.2.0-universal.jar`,
+  /// None when the file name doesn't match the artifact id and version of its folders
+  pub fn from_path(path: &str) -> Option<Artifact> {
+    Self::from_relative_path(Path::new(path.trim_start_matches('/'))).ok()
+  }
+
+  /// Inverse of [`Self::get_path_vec`]: the group is every folder but the last two, then the artifact id
+  /// and version, and the classifier and extension come from the file name. `path` is relative to the
+  /// libraries folder.
+  pub fn from_relative_path(path: &Path) -> Result<Artifact, String> {
+    let parts = path
+      .components()
+      .map(|part| part.as_os_str().to_str().ok_or_else(|| format!("Non UTF-8 path: {}", path.display())))
+      .collect::<Result<Vec<_>, _>>()?;
+    let [group @ .., artifact_id, version, file] = parts.as_slice() else {
+      return Err(format!("Too few components for an artifact path: {}", path.display()));
+    };
+    if group.is_empty() {
+      return Err(format!("Missing group in artifact path: {}", path.display()));
+    }
+    let (name, ext) = file.rsplit_once('.').ok_or_else(|| format!("Missing extension in artifact path: {}", path.display()))?;
+    let classifier = match name.strip_prefix(&format!("{artifact_id}-{version}")) {
+      Some("") => None,
+      Some(rest) if rest.len() > 1 && rest.starts_with('-') => Some(rest[1..].to_string()),
+      _ => Err(format!("File name {file} doesn't match {artifact_id} {version}"))?,
+    };
+    Ok(Artifact {
+      original_descriptor: None,
+      group_id: group.iter().map(|part| part.to_string()).collect(),
+      artifact_id: artifact_id.to_string(),
+      version: version.to_string(),
+      classifier,
+      ext: ext.to_string(),
+    })
+  }
+
+  /// Same coordinates with another classifier, e.g. the `universal` jar of a forge artifact
+  pub fn with_classifier(&self, classifier: Option<&str>) -> Artifact {
+    Artifact {
+      original_descriptor: None,
+      classifier: classifier.map(str::to_string),
+      ..self.clone()
+    }
+  }
+
+  pub fn with_ext(&self, ext: &str) -> Artifact {
+    Artifact {
+      original_descriptor: None,
+      ext: ext.to_string(),
+      ..self.clone()
+    }
+  }
 }
 
-impl TryFrom<String> for Artifact {
-  type Error = String;
-  fn try_from(og_value: String) -> Result<Self, Self::Error> {
-    let value = og_value.clone();
-    let (value, ext) = value.split_once("@").unwrap_or((&value, "jar"));
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ArtifactParseError {
+  #[error("Invalid artifact descriptor, expected group:artifact:version[:classifier][@ext]: {0}")] TooFewParts(String),
+  #[error("Invalid artifact descriptor, expected at most group:artifact:version:classifier[@ext]: {0}")] TooManyParts(String),
+  #[error("Invalid artifact descriptor, empty group: {0}")] EmptyGroup(String),
+  #[error("Invalid artifact descriptor, empty artifact id: {0}")] EmptyArtifactId(String),
+  #[error("Invalid artifact descriptor, empty version: {0}")] EmptyVersion(String),
+}
+
+/// Parses `group:artifact:version[:classifier][@ext]`, the extension defaulting to `jar`. A fifth part
+/// is rejected rather than dropped or folded into the classifier, so it can't silently point to another file.
+impl FromStr for Artifact {
+  type Err = ArtifactParseError;
+  fn from_str(og_value: &str) -> Result<Self, Self::Err> {
+    let (value, ext) = og_value.split_once("@").unwrap_or((og_value, "jar"));
 
     let parts: Vec<&str> = value.split(":").collect();
     if parts.len() < 3 {
-      return Err(format!("Invalid artifact path: {}", value));
+      return Err(ArtifactParseError::TooFewParts(og_value.to_string()));
+    }
+    if parts.len() > 4 {
+      return Err(ArtifactParseError::TooManyParts(og_value.to_string()));
+    }
+    if parts[0].is_empty() || parts[0].split(".").any(|s| s.is_empty()) {
+      return Err(ArtifactParseError::EmptyGroup(og_value.to_string()));
+    }
+    if parts[1].is_empty() {
+      return Err(ArtifactParseError::EmptyArtifactId(og_value.to_string()));
+    }
+    if parts[2].is_empty() {
+      return Err(ArtifactParseError::EmptyVersion(og_value.to_string()));
     }
     let group_id: Vec<String> = parts[0]
       .split(".")
@@ -96,7 +202,7 @@ impl TryFrom<String> for Artifact {
     let version = parts[2].to_string();
     let classifier = parts.get(3).map(|s| s.to_string());
     Ok(Self {
-      original_descriptor: Some(og_value),
+      original_descriptor: Some(og_value.to_string()),
       group_id,
       artifact_id,
       version,
@@ -106,6 +212,13 @@ impl TryFrom<String> for Artifact {
   }
 }
 
+impl TryFrom<String> for Artifact {
+  type Error = ArtifactParseError;
+  fn try_from(value: String) -> Result<Self, Self::Error> {
+    value.parse()
+  }
+}
+
 impl Into<String> for Artifact {
   fn into(self) -> String {
     self.get_descriptor()
@@ -123,11 +236,33 @@ impl Sha1Sum {
 
   pub fn from_reader<T: Read>(value: &mut T) -> Result<Self, Box<dyn std::error::Error>> {
     let mut sha1_hasher = Sha1::new();
-    let mut buf = vec![];
-    value.read_to_end(&mut buf)?;
-    sha1_hasher.update(&buf);
+    read_chunks(value, |chunk| sha1_hasher.update(chunk))?;
+    Ok(Sha1Sum(sha1_hasher.finalize().into()))
+  }
+
+  /// Hashes the file at `path` without keeping the whole file in memory
+  pub fn from_file(path: &Path) -> io::Result<Self> {
+    let mut sha1_hasher = Sha1::new();
+    read_chunks(&mut fs::File::open(path)?, |chunk| sha1_hasher.update(chunk))?;
     Ok(Sha1Sum(sha1_hasher.finalize().into()))
   }
+
+  /// Whether the file at `path` hashes to `expected`
+  pub fn verify_file(path: &Path, expected: &Sha1Sum) -> io::Result<bool> {
+    Ok(&Self::from_file(path)? == expected)
+  }
+
+  /// Hashes a file on the blocking thread pool, so it can be awaited without stalling the executor
+  pub async fn from_file_async(path: &Path) -> Result<Self, io::Error> {
+    let mut file = tokio::fs::File::open(path).await?.into_std().await;
+    tokio::task
+      ::spawn_blocking(move || {
+        let mut sha1_hasher = Sha1::new();
+        read_chunks(&mut file, |chunk| sha1_hasher.update(chunk))?;
+        Ok(Sha1Sum(sha1_hasher.finalize().into()))
+      }).await
+      .map_err(|err| io::Error::new(ErrorKind::Other, err))?
+  }
 }
 
 impl TryFrom<String> for Sha1Sum {
@@ -157,6 +292,115 @@ impl Display for Sha1Sum {
   }
 }
 
+/// Feeds the reader to `consumer` in fixed-size chunks, so memory use doesn't depend on its length
+fn read_chunks<T: Read>(value: &mut T, mut consumer: impl FnMut(&[u8])) -> Result<(), std::io::Error> {
+  let mut buf = vec![0u8; 64 * 1024];
+  loop {
+    let read = match value.read(&mut buf) {
+      Ok(0) => return Ok(()),
+      Ok(read) => read,
+      Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+      Err(err) => return Err(err),
+    };
+    consumer(&buf[..read]);
+  }
+}
+
+/// Ordered from weakest to strongest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChecksumAlgorithm {
+  Sha1,
+  Sha256,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Checksum {
+  Sha1(Sha1Sum),
+  Sha256([u8; 32]),
+}
+
+impl Checksum {
+  pub fn from_reader_with<T: Read>(algorithm: ChecksumAlgorithm, value: &mut T) -> Result<Self, Box<dyn std::error::Error>> {
+    let mut hasher = ChecksumHasher::new(algorithm);
+    hasher.update_from_reader(value)?;
+    Ok(hasher.finalize())
+  }
+
+  pub fn get_algorithm(&self) -> ChecksumAlgorithm {
+    match self {
+      Self::Sha1(_) => ChecksumAlgorithm::Sha1,
+      Self::Sha256(_) => ChecksumAlgorithm::Sha256,
+    }
+  }
+
+  /// Hashes the reader with this checksum's algorithm and compares the result
+  pub fn verify<T: Read>(&self, value: &mut T) -> bool {
+    Self::from_reader_with(self.get_algorithm(), value).is_ok_and(|sum| &sum == self)
+  }
+
+  /// Strongest algorithm present in the list, which is the one that should be used for verification
+  pub fn get_strongest_algorithm(checksums: &[Checksum]) -> Option<ChecksumAlgorithm> {
+    checksums
+      .iter()
+      .map(|sum| sum.get_algorithm())
+      .max()
+  }
+}
+
+impl From<Sha1Sum> for Checksum {
+  fn from(value: Sha1Sum) -> Self {
+    Self::Sha1(value)
+  }
+}
+
+impl Debug for Checksum {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self)
+  }
+}
+
+impl Display for Checksum {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Sha1(sum) => write!(f, "sha1:{sum}"),
+      Self::Sha256(sum) => write!(f, "sha256:{}", hex::encode(sum)),
+    }
+  }
+}
+
+/// Incremental hasher for a [`ChecksumAlgorithm`], used when the data arrives in chunks
+pub enum ChecksumHasher {
+  Sha1(Sha1),
+  Sha256(Sha256),
+}
+
+impl ChecksumHasher {
+  pub fn new(algorithm: ChecksumAlgorithm) -> Self {
+    match algorithm {
+      ChecksumAlgorithm::Sha1 => Self::Sha1(Sha1::new()),
+      ChecksumAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+    }
+  }
+
+  pub fn update(&mut self, data: &[u8]) {
+    match self {
+      Self::Sha1(hasher) => hasher.update(data),
+      Self::Sha256(hasher) => hasher.update(data),
+    }
+  }
+
+  pub fn update_from_reader<T: Read>(&mut self, value: &mut T) -> Result<(), std::io::Error> {
+    read_chunks(value, |chunk| self.update(chunk))
+  }
+
+  pub fn finalize(self) -> Checksum {
+    match self {
+      Self::Sha1(hasher) => Checksum::Sha1(Sha1Sum::new(hasher.finalize().into())),
+      Self::Sha256(hasher) => Checksum::Sha256(hasher.finalize().into()),
+    }
+  }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 struct PartialVersion {
@@ -168,54 +412,242 @@ struct PartialVersion {
   pub url: String,
 }
 
-async fn download_manifest() -> Result<Vec<PartialVersion>, Box<dyn std::error::Error>> {
-  const URL: &str = "https://piston-meta.mojang.com/mc/game/version_manifest_v2.json";
-  let response: Value = Client::new().get(URL).send().await?.json().await?;
+/// Base urls of every remote the installer talks to, so they can be pointed at internal mirrors
+#[derive(Debug, Clone)]
+pub struct Repositories {
+  pub forge_maven: String,
+  pub neoforge_maven: String,
+  /// Host of Forge's maven-metadata.json and promotions_slim.json
+  pub forge_files: String,
+  pub mojang_meta: String,
+  /// Used for libraries that don't specify their own url
+  pub libraries_base: String,
+  /// Headers (e.g. `Authorization`) sent to every host matching the pattern, either a host name or
+  /// `*.domain` for its subdomains. Used by authenticated mirrors.
+  pub headers: Vec<(String, HeaderMap)>,
+}
+
+impl Default for Repositories {
+  fn default() -> Self {
+    Self {
+      forge_maven: "https://maven.minecraftforge.net/".to_string(),
+      neoforge_maven: "https://maven.neoforged.net/releases/".to_string(),
+      forge_files: "https://files.minecraftforge.net/".to_string(),
+      mojang_meta: "https://piston-meta.mojang.com/".to_string(),
+      libraries_base: "https://libraries.minecraft.net/".to_string(),
+      headers: vec![],
+    }
+  }
+}
+
+impl Repositories {
+  /// Every header configured for the host of `url`, later patterns overriding earlier ones
+  pub fn get_headers(&self, url: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let Some(host) = reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string)) else {
+      return headers;
+    };
+    for (pattern, pattern_headers) in &self.headers {
+      let matches = match pattern.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{domain}")),
+        None => &host == pattern,
+      };
+      if matches {
+        headers.extend(pattern_headers.clone());
+      }
+    }
+    headers
+  }
+}
+
+/// Joins a base url and a path, regardless of whether the base ends with a slash
+pub fn join_url(base: &str, path: &str) -> String {
+  format!("{}/{}", base.trim_end_matches('/'), path.trim_start_matches('/'))
+}
+
+async fn download_manifest(repositories: &Repositories) -> Result<Vec<PartialVersion>, Box<dyn std::error::Error>> {
+  let url = join_url(&repositories.mojang_meta, "mc/game/version_manifest_v2.json");
+  let response: Value = download_utils::metadata_client().get(&url).headers(repositories.get_headers(&url)).send().await?.json().await?;
   let versions = response.get("versions").unwrap();
   Ok(serde_json::from_value(versions.clone())?)
 }
 
-pub async fn get_vanilla_version(mc_version: &str, json_path: &PathBuf) -> Option<Value> {
-  let bytes = if json_path.is_file() {
-    fs::read(json_path).ok()?
-  } else {
-    let versions = download_manifest().await.ok()?;
-    let url = versions.into_iter().find(|v| v.id == mc_version)?.url;
-    let bytes = Client::new().get(url).send().await.ok()?.bytes().await.ok()?;
-    fs::write(json_path, &bytes).ok()?;
-    bytes.to_vec()
-  };
-  Some(serde_json::from_slice(&bytes).ok()?)
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedManifest {
+  fetched_at: DateTime<Utc>,
+  versions: Vec<PartialVersion>,
+}
+
+/// On-disk cache of the Mojang version manifest, refetched once it's older than `ttl`. Each meta mirror
+/// gets its own file next to `path`, see [`Self::get_path`].
+#[derive(Debug, Clone)]
+pub struct ManifestCache {
+  pub path: PathBuf,
+  pub ttl: Duration,
+}
+
+impl Default for ManifestCache {
+  fn default() -> Self {
+    Self {
+      path: env::temp_dir().join("forge_downloader").join("version_manifest_v2.json"),
+      ttl: Duration::from_secs(60 * 60),
+    }
+  }
+}
+
+impl ManifestCache {
+  pub fn new(path: PathBuf, ttl: Duration) -> Self {
+    Self { path, ttl }
+  }
+
+  /// `path` with a hash of the meta url of `repositories` in its name, so each mirror has its own cache
+  pub fn get_path(&self, repositories: &Repositories) -> PathBuf {
+    let hash = Sha1Sum(Sha1::digest(repositories.mojang_meta.as_bytes()).into()).to_string();
+    let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = self.path.extension().map(|extension| format!(".{}", extension.to_string_lossy())).unwrap_or_default();
+    self.path.with_file_name(format!("{stem}-{}{extension}", &hash[..8]))
+  }
+
+  fn read(&self, path: &Path) -> Option<Vec<PartialVersion>> {
+    let cached: CachedManifest = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+    let age = Utc::now().signed_duration_since(cached.fetched_at).to_std().ok()?;
+    if age < self.ttl { Some(cached.versions) } else { None }
+  }
+
+  fn write(&self, path: &Path, versions: &[PartialVersion]) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(parent) = path.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    let cached = CachedManifest { fetched_at: Utc::now(), versions: versions.to_vec() };
+    fs::write(path, serde_json::to_vec(&cached)?)?;
+    Ok(())
+  }
+
+  async fn get_versions(&self, repositories: &Repositories) -> Result<Vec<PartialVersion>, Box<dyn std::error::Error>> {
+    let path = self.get_path(repositories);
+    if let Some(versions) = self.read(&path) {
+      return Ok(versions);
+    }
+    let versions = download_manifest(repositories).await?;
+    if let Err(err) = self.write(&path, &versions) {
+      warn!("Failed to cache version manifest at {}: {err}", path.display());
+    }
+    Ok(versions)
+  }
+}
+
+pub async fn get_vanilla_version(mc_version: &str, json_path: &Path) -> Option<Value> {
+  get_vanilla_version_with_cache(mc_version, json_path, &ManifestCache::default(), &Repositories::default()).await
+}
+
+pub async fn get_vanilla_version_with_cache(
+  mc_version: &str,
+  json_path: &Path,
+  cache: &ManifestCache,
+  repositories: &Repositories
+) -> Option<Value> {
+  if json_path.is_file() {
+    match fs::read(json_path).ok().and_then(|bytes| parse_vanilla_version(mc_version, &bytes)) {
+      Some(json) => return Some(json),
+      None => {
+        warn!("Cached version json {} is invalid or not {mc_version}, downloading it again", json_path.display());
+        let _ = fs::remove_file(json_path);
+      }
+    }
+  }
+  let versions = cache.get_versions(repositories).await.ok()?;
+  let url = versions.into_iter().find(|v| v.id == mc_version)?.url;
+  let request = download_utils::metadata_client().get(&url).headers(repositories.get_headers(&url));
+  let bytes = request.send().await.ok()?.bytes().await.ok()?;
+  let json = parse_vanilla_version(mc_version, &bytes)?;
+  fs::write(json_path, &bytes).ok()?;
+  Some(json)
+}
+
+/// Only accepts a version json whose `id` is `mc_version`
+fn parse_vanilla_version(mc_version: &str, bytes: &[u8]) -> Option<Value> {
+  let json: Value = serde_json::from_slice(bytes).ok()?;
+  (json["id"].as_str() == Some(mc_version)).then_some(json)
 }
 
 #[cfg(test)]
 mod tests {
   use futures::future::join_all;
+  use reqwest::Client;
   use zip::ZipArchive;
 
-  use crate::forge_installer_profile::{ ForgeInstallerProfile, v2::ForgeInstallerProfileV2, v1::ForgeInstallerProfileV1 };
+  use crate::forge_installer_profile::{
+    source::ForgeInstallProfileSource,
+    ForgeInstallerProfile,
+    v2::{ ForgeInstallerProfileV2, ForgeVersionFileV2, JvmOptions, MojangLibrary, ProcessorEnv, TargetArch, TargetOs },
+    v1::{ ForgeInstallerProfileV1, ForgeOptional },
+  };
 
-  use super::{ *, download_utils::forge::ForgeVersionHandler, forge_client_install::ForgeClientInstall };
-  use std::{ env::temp_dir, io::{ Cursor, Write }, fs::File, str::FromStr };
+  use super::{ *, download_utils::forge::{ build_promoted_versions, compare_neo_versions, Channel, ForgeVersionHandler, ForgeVersionInfo, Loader, parse_neoforge_version, parse_promotions }, forge_client_install::{ ForgeClientInstall, PlannedAction } };
+  use std::{ collections::HashMap, env::temp_dir, io::{ Cursor, Write }, fs::File, ops::Deref, str::FromStr };
 
-  #[tokio::test]
-  async fn install_test() -> Result<(), Box<dyn std::error::Error>> {
-    let versions = ForgeVersionHandler::new().await?;
-    let version = versions.get_best_version("1.20.1").unwrap();
+  /// Directory of a single test, unique to this run and removed when dropped, even if the test fails
+  struct TestDir(PathBuf);
 
-    let url = version.get_installer_url();
-    println!("Installer jar url: {}", url);
+  impl TestDir {
+    fn new(name: &str) -> Self {
+      let dir = temp_dir().join(format!("{name}-{}", std::process::id()));
+      Self(dir)
+    }
+  }
 
-    let response = Client::new().get(&url).send().await?;
-    if !response.status().is_success() {
-      println!("Couldn't download: {}", response.status());
-      return Ok(());
+  impl Deref for TestDir {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf {
+      &self.0
+    }
+  }
+
+  impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+      &self.0
     }
-    let bytes = response.bytes().await?;
+  }
+
+  impl Drop for TestDir {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.0);
+    }
+  }
+
+  /// An installer archive without any entry
+  fn empty_installer() -> ForgeInstallProfileSource<Cursor<Vec<u8>>> {
+    let mut empty = Cursor::new(vec![]);
+    zip::ZipWriter::new(&mut empty).finish().unwrap();
+    ForgeInstallProfileSource::Zip(ZipArchive::new(empty).unwrap())
+  }
+
+  /// A V2 install profile of 1.20.1-forge-47.2.0 without libraries, processors or data, `fields` replacing
+  /// its defaults
+  fn profile_json(fields: serde_json::Value) -> String {
+    let mut profile = serde_json::json!({
+      "spec": 1, "profile": "forge", "version": "1.20.1-forge-47.2.0", "json": "/version.json", "logo": "/big_logo.png",
+      "minecraft": "1.20.1", "welcome": "Welcome", "libraries": [], "processors": [], "data": {}
+    });
+    for (key, value) in fields.as_object().unwrap() {
+      profile[key] = value.clone();
+    }
+    profile.to_string()
+  }
+
+  #[tokio::test]
+  async fn install_test()-> Result<(), Box<dyn std::error::Error>> {
+    let versions = ForgeVersionHandler::new(Loader::Forge).await?;
+    let version = versions.get_best_version("1.20.1").unwrap();
+
+    println!("Installer jar url: {}", version.get_installer_url());
+    let bytes = version.download_installer(&Client::new()).await?;
 
     let game_dir = temp_dir().join(".minecraft-core-test"); //Path::new(env!("APPDATA")).join(".minecraft");
 
-    fs::create_dir_all(&temp_dir())?;
+    fs::create_dir_all(temp_dir())?;
     let installer_path = temp_dir().join("forge-installer.jar");
 
     fs::write(&installer_path, bytes)?;
@@ -232,12 +664,1475 @@ mod tests {
     Ok(())
   }
 
+  #[test]
+  fn test_neoforge_version() {
+    let version = parse_neoforge_version("20.4.80-beta").unwrap();
+    assert_eq!(version.mc_version, "1.20.4");
+    assert_eq!(version.get_full_version(), "20.4.80-beta");
+    assert_eq!(
+      version.get_installer_url(),
+      "https://maven.neoforged.net/releases/net/neoforged/neoforge/20.4.80-beta/neoforge-20.4.80-beta-installer.jar"
+    );
+    assert_eq!(parse_neoforge_version("21.0.1").unwrap().mc_version, "1.21");
+    assert!(parse_neoforge_version("snapshot").is_none());
+
+    let mut versions = ["20.4.9", "20.4.10", "20.4.99", "20.4.80-beta", "20.4.237-beta", "20.6.1-beta"]
+      .iter()
+      .filter_map(|version| parse_neoforge_version(version))
+      .collect::<Vec<_>>();
+    build_promoted_versions(&mut versions);
+    let handler = ForgeVersionHandler { loader: Loader::NeoForge, versions };
+    let stable = handler.get_best_version_for_channel("1.20.4", Channel::Stable).unwrap();
+    assert_eq!(stable.get_full_version(), "20.4.99");
+    assert!(stable.recommended);
+    let beta = handler.get_best_version_for_channel("1.20.4", Channel::Beta).unwrap();
+    assert_eq!(beta.get_full_version(), "20.4.237-beta");
+    assert!(beta.latest);
+    assert_eq!(handler.get_best_version("1.20.4").unwrap().get_full_version(), "20.4.99");
+    assert!(handler.get_best_version_for_channel("1.20.6", Channel::Stable).is_none());
+    assert_eq!(handler.get_best_version_for_channel("1.20.6", Channel::Beta).unwrap().get_full_version(), "20.6.1-beta");
+
+    assert_eq!(compare_neo_versions("20.4.200", "20.4.99"), std::cmp::Ordering::Greater);
+    assert_eq!(compare_neo_versions("20.4.80-beta", "20.4.80"), std::cmp::Ordering::Equal);
+    let mut versions = ["20.4.99", "20.4.200"]
+      .iter()
+      .filter_map(|version| parse_neoforge_version(version))
+      .collect::<Vec<_>>();
+    build_promoted_versions(&mut versions);
+    let latest = versions.iter().find(|version| version.latest).unwrap();
+    assert_eq!(latest.forge_version, "20.4.200");
+  }
+
+  #[test]
+  fn test_forge_promotions() {
+    let promotions = parse_promotions(
+      &serde_json::json!({ "promos": { "1.20.1-latest": "47.2.20", "1.20.1-recommended": "47.2.0", "1.7.10-latest": null } })
+    );
+    assert_eq!(promotions.len(), 2);
+    assert_eq!(promotions["1.20.1-recommended"], "47.2.0");
+    assert!(parse_promotions(&serde_json::json!({})).is_empty());
+
+    let version = |forge_version: &str, latest: bool, recommended: bool| ForgeVersionInfo {
+      loader: Loader::Forge,
+      mc_version: "1.20.1".to_string(),
+      forge_version: forge_version.to_string(),
+      suffix: None,
+      latest,
+      recommended,
+    };
+    let mut handler = ForgeVersionHandler { loader: Loader::Forge, versions: vec![version("47.2.20", true, false)] };
+    assert_eq!(handler.get_best_version("1.20.1").unwrap().forge_version, "47.2.20");
+    handler.versions.push(version("47.2.0", false, true));
+    assert_eq!(handler.get_best_version("1.20.1").unwrap().forge_version, "47.2.0");
+    assert!(handler.get_best_version("1.19.2").is_none());
+  }
+
+  #[test]
+  fn test_parse_vanilla_version() {
+    assert!(parse_vanilla_version("1.20.1", br#"{ "id": "1.20.1" }"#).is_some());
+    assert!(parse_vanilla_version("1.20.1", br#"{ "id": "1.19.2" }"#).is_none());
+    assert!(parse_vanilla_version("1.20.1", br#"{ "id": "1.20.1""#).is_none());
+  }
+
+  #[tokio::test]
+  async fn test_cached_versions() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TestDir::new("forge_cached_versions");
+    let cache_path = dir.join("forge.json");
+    assert!(ForgeVersionHandler::new_offline(Loader::Forge, &cache_path).is_err());
+
+    fs::create_dir_all(&*dir)?;
+    let cached = serde_json::json!({
+      "fetchedAt": Utc::now(),
+      "loader": "Forge",
+      "versions": [
+        { "loader": "Forge", "mc_version": "1.20.1", "forge_version": "47.2.0", "suffix": null, "latest": true, "recommended": true }
+      ]
+    });
+    fs::write(&cache_path, serde_json::to_vec(&cached)?)?;
+    let handler = ForgeVersionHandler::new_cached(Loader::Forge, &cache_path, Duration::from_secs(60)).await?;
+    assert_eq!(handler.get_best_version("1.20.1").unwrap().forge_version, "47.2.0");
+    assert_eq!(ForgeVersionHandler::new_offline(Loader::Forge, &cache_path)?.versions.len(), 1);
+    assert!(ForgeVersionHandler::new_offline(Loader::NeoForge, &cache_path).is_err());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_manifest_cache_per_mirror() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TestDir::new("forge_manifest_cache");
+    let cache = ManifestCache::new(dir.join("version_manifest_v2.json"), Duration::from_secs(60));
+    let mirror = Repositories { mojang_meta: "https://meta.invalid/".to_string(), ..Default::default() };
+    let (official, mirrored) = (cache.get_path(&Repositories::default()), cache.get_path(&mirror));
+    assert_ne!(official, mirrored);
+    assert!(mirrored.file_name().unwrap().to_str().unwrap().starts_with("version_manifest_v2-"));
+
+    let version = PartialVersion {
+      id: "1.20.1".to_string(),
+      time: Utc::now(),
+      release_time: Utc::now(),
+      release_type: "release".to_string(),
+      url: "https://meta.invalid/1.20.1.json".to_string(),
+    };
+    cache.write(&official, &[version])?;
+    assert_eq!(cache.get_versions(&Repositories::default()).await?.len(), 1);
+    // Cached for the official meta only, so the mirror is still fetched
+    assert!(cache.get_versions(&mirror).await.is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_mc_version_search() {
+    let versions = ["1.20.1", "1.2.5", "1.20", "1.7.10_pre4", "1.7.10", "1.9"]
+      .into_iter()
+      .map(|mc_version| ForgeVersionInfo {
+        loader: Loader::Forge,
+        mc_version: mc_version.to_string(),
+        forge_version: "1.0".to_string(),
+        suffix: None,
+        latest: true,
+        recommended: false,
+      })
+      .collect();
+    let handler = ForgeVersionHandler { loader: Loader::Forge, versions };
+    assert_eq!(handler.get_all_mc_versions(), vec!["1.2.5", "1.7.10", "1.7.10_pre4", "1.9", "1.20", "1.20.1"]);
+    assert_eq!(handler.get_latest_mc_version().as_deref(), Some("1.20.1"));
+    assert_eq!(handler.get_by_mc_version_prefix("1.20.x").len(), 2);
+    assert_eq!(handler.get_by_mc_version_prefix("1.2").len(), 1);
+  }
+
+  #[test]
+  fn test_mirror_url() {
+    let url = "https://maven.minecraftforge.net/net/minecraftforge/forge/1.20.1-47.2.0/forge-1.20.1-47.2.0-universal.jar";
+    assert_eq!(
+      download_utils::mirror_url(url, "https://mirror.example.com").unwrap(),
+      "https://mirror.example.com/net/minecraftforge/forge/1.20.1-47.2.0/forge-1.20.1-47.2.0-universal.jar"
+    );
+    assert_eq!(
+      download_utils::mirror_url(url, "https://mirror.example.com/maven/").unwrap(),
+      "https://mirror.example.com/maven/net/minecraftforge/forge/1.20.1-47.2.0/forge-1.20.1-47.2.0-universal.jar"
+    );
+    assert!(download_utils::mirror_url(url, "not a url").is_none());
+  }
+
+  #[test]
+  fn test_repository_headers() {
+    use reqwest::header::{ HeaderValue, AUTHORIZATION };
+    let mut auth = HeaderMap::new();
+    auth.insert(AUTHORIZATION, HeaderValue::from_static("Bearer token"));
+    let repositories = Repositories {
+      headers: vec![("nexus.example.com".to_string(), auth.clone()), ("*.internal.dev".to_string(), auth)],
+      ..Repositories::default()
+    };
+    assert_eq!(repositories.get_headers("https://nexus.example.com/maven/a.jar")[AUTHORIZATION], "Bearer token");
+    assert!(repositories.get_headers("https://maven.internal.dev/a.jar").contains_key(AUTHORIZATION));
+    assert!(repositories.get_headers("https://internal.dev/a.jar").is_empty());
+    assert!(repositories.get_headers("https://maven.minecraftforge.net/a.jar").is_empty());
+    assert!(repositories.get_headers("not a url").is_empty());
+  }
+
+  #[test]
+  fn test_retryable_errors() {
+    use download_utils::is_retryable;
+    for kind in [io::ErrorKind::TimedOut, io::ErrorKind::ConnectionReset, io::ErrorKind::UnexpectedEof] {
+      assert!(is_retryable(&io::Error::new(kind, "interrupted")));
+    }
+    assert!(!is_retryable(&io::Error::new(io::ErrorKind::PermissionDenied, "denied")));
+    assert!(is_retryable(&forge_client_install::ForgeInstallError::Status("https://a/".to_string(), 503)));
+    assert!(!is_retryable(&forge_client_install::ForgeInstallError::Status("https://a/".to_string(), 404)));
+    // A url that can't be parsed fails the same way on every attempt
+    let builder = Client::new().get("not a url").build().unwrap_err();
+    assert!(!is_retryable(&builder));
+  }
+
+  #[tokio::test]
+  async fn test_host_limiter() {
+    let limiter = download_utils::HostLimiter::default();
+    let permit = limiter.acquire("https://maven.minecraftforge.net/a.jar", 1).await;
+    assert!(permit.is_some());
+    let wait = Duration::from_millis(50);
+    let same_host = tokio::time::timeout(wait, limiter.acquire("https://maven.minecraftforge.net/b.jar", 1)).await;
+    assert!(same_host.is_err());
+    let other_host = tokio::time::timeout(wait, limiter.acquire("https://maven.neoforged.net/a.jar", 1)).await;
+    assert!(other_host.is_ok_and(|permit| permit.is_some()));
+    drop(permit);
+    let same_host = tokio::time::timeout(wait, limiter.clone().acquire("https://maven.minecraftforge.net/b.jar", 1)).await;
+    assert!(same_host.is_ok_and(|permit| permit.is_some()));
+    assert!(limiter.acquire("not a url", 1).await.is_none());
+  }
+
+  #[test]
+  fn test_java_version_parse() {
+    let java17 = "openjdk version \"17.0.6\" 2023-01-17\nOpenJDK Runtime Environment Temurin-17.0.6+10 (build 17.0.6+10)";
+    assert_eq!(java::JavaVersion::parse(java17).map(|v| v.major), Some(17));
+    let java8 = "java version \"1.8.0_351\"\nJava(TM) SE Runtime Environment (build 1.8.0_351-b10)";
+    assert_eq!(java::JavaVersion::parse(java8).map(|v| v.major), Some(8));
+    assert!(java::JavaVersion::parse("bash: java: command not found").is_none());
+  }
+
+  #[test]
+  fn test_native_library() -> Result<(), Box<dyn std::error::Error>> {
+    let library: MojangLibrary = serde_json::from_str(
+      r#"{
+        "name": "org.lwjgl.lwjgl:lwjgl-platform:2.9.4-nightly-20150209",
+        "downloads": {
+          "classifiers": {
+            "natives-linux": { "path": "a.jar", "url": "https://libraries.minecraft.net/a.jar" },
+            "natives-windows": { "path": "b.jar", "url": "https://libraries.minecraft.net/b.jar" }
+          }
+        },
+        "natives": { "linux": "natives-linux", "windows": "natives-windows", "osx": "natives-osx" }
+      }"#
+    )?;
+    let (artifact, download) = library.get_native(&TargetOs::Windows, &TargetArch::X86_64).unwrap();
+    assert_eq!(artifact.get_file(), "lwjgl-platform-2.9.4-nightly-20150209-natives-windows.jar");
+    assert_eq!(download.url.as_deref(), Some("https://libraries.minecraft.net/b.jar"));
+    assert!(library.get_native(&TargetOs::Osx, &TargetArch::X86_64).is_none());
+    // Only ships natives, so there's no main artifact to place
+    assert_eq!(library.get_artifacts(&TargetOs::Windows, &TargetArch::X86_64).len(), 1);
+
+    let library: MojangLibrary = serde_json::from_str(
+      r#"{
+        "name": "org.lwjgl:lwjgl:3.2.2",
+        "downloads": {
+          "artifact": { "path": "lwjgl.jar", "url": "https://libraries.minecraft.net/lwjgl.jar" },
+          "classifiers": {
+            "natives-linux": { "path": "linux.jar", "url": "https://libraries.minecraft.net/linux.jar" },
+            "natives-windows": { "path": "windows.jar", "url": "https://libraries.minecraft.net/windows.jar" }
+          }
+        },
+        "natives": { "linux": "natives-linux", "windows": "natives-windows" }
+      }"#
+    )?;
+    let artifacts = library.get_artifacts(&TargetOs::Linux, &TargetArch::X86_64);
+    let files = artifacts.iter().map(|(artifact, _)| artifact.get_file()).collect::<Vec<_>>();
+    assert_eq!(files, vec!["lwjgl-3.2.2.jar", "lwjgl-3.2.2-natives-linux.jar"]);
+    assert_eq!(artifacts[1].1.url.as_deref(), Some("https://libraries.minecraft.net/linux.jar"));
+
+    // `${arch}` follows the target, not the host
+    let library: MojangLibrary = serde_json::from_str(
+      r#"{
+        "name": "tv.twitch:twitch-platform:5.16",
+        "downloads": {
+          "classifiers": {
+            "natives-windows-32": { "path": "32.jar", "url": "https://libraries.minecraft.net/32.jar" },
+            "natives-windows-64": { "path": "64.jar", "url": "https://libraries.minecraft.net/64.jar" }
+          }
+        },
+        "natives": { "windows": "natives-windows-${arch}" }
+      }"#
+    )?;
+    assert_eq!(library.get_native(&TargetOs::Windows, &TargetArch::X86).unwrap().1.url.as_deref(), Some("https://libraries.minecraft.net/32.jar"));
+    assert_eq!(library.get_native(&TargetOs::Windows, &TargetArch::Arm64).unwrap().1.url.as_deref(), Some("https://libraries.minecraft.net/64.jar"));
+    Ok(())
+  }
+
+  #[test]
+  fn test_hybrid_library() -> Result<(), Box<dyn std::error::Error>> {
+    use forge_installer_profile::ForgeVersionLibrary;
+    let hybrid: ForgeVersionLibrary = serde_json::from_str(
+      r#"{
+        "name": "net.minecraftforge:forge:1.20.1-47.2.0:universal", "url": "https://maven.minecraftforge.net/",
+        "downloads": { "artifact": { "path": "net/minecraftforge/forge/1.20.1-47.2.0/forge-1.20.1-47.2.0-universal.jar", "url": "", "size": 3 } }
+      }"#
+    )?;
+    let mojang = hybrid.to_mojang().unwrap();
+    assert_eq!(mojang.url.as_deref(), Some("https://maven.minecraftforge.net/"));
+    assert_eq!(mojang.get_size(), Some(3));
+    assert!(serde_json::to_value(&hybrid)?.get("url").is_some());
+
+    // A broken downloads section is an error instead of a Forge library without downloads
+    let broken = serde_json::from_str::<ForgeVersionLibrary>(r#"{ "name": "org.ow2.asm:asm:9.5", "downloads": { "artifact": { "sha1": "nope" } } }"#);
+    assert!(broken.is_err());
+    let forge: ForgeVersionLibrary = serde_json::from_str(r#"{ "name": "org.ow2.asm:asm:9.5", "url": "https://maven.minecraftforge.net/" }"#)?;
+    assert!(forge.to_forge().is_some());
+    assert_eq!(forge.get_name().get_descriptor(), "org.ow2.asm:asm:9.5");
+    assert_eq!(hybrid.get_name(), &mojang.name);
+    Ok(())
+  }
+
+  #[test]
+  fn test_library_rules() -> Result<(), Box<dyn std::error::Error>> {
+    let library = |rules: &str| -> Result<MojangLibrary, serde_json::Error> {
+      serde_json::from_str(&format!(r#"{{ "name": "org.lwjgl:lwjgl:3.3.1", "downloads": {{}}, "rules": {rules} }}"#))
+    };
+    let mac_only = library(r#"[{ "action": "allow", "os": { "name": "osx" } }]"#)?;
+    assert!(mac_only.is_allowed(&TargetOs::Osx, &TargetArch::X86_64));
+    assert!(!mac_only.is_allowed(&TargetOs::Windows, &TargetArch::X86_64));
+
+    let not_mac = library(r#"[{ "action": "allow" }, { "action": "disallow", "os": { "name": "osx" } }]"#)?;
+    assert!(!not_mac.is_allowed(&TargetOs::Osx, &TargetArch::X86_64));
+    assert!(not_mac.is_allowed(&TargetOs::Linux, &TargetArch::X86_64));
+
+    let demo = library(r#"[{ "action": "allow", "features": { "is_demo_user": true } }]"#)?;
+    assert!(!demo.is_allowed(&TargetOs::Windows, &TargetArch::X86_64));
+    assert!(library("null")?.is_allowed(&TargetOs::Windows, &TargetArch::X86_64));
+
+    let x86_only = library(r#"[{ "action": "allow", "os": { "arch": "x86" } }]"#)?;
+    assert!(x86_only.is_allowed(&TargetOs::Windows, &TargetArch::X86));
+    assert!(!x86_only.is_allowed(&TargetOs::Windows, &TargetArch::X86_64));
+    Ok(())
+  }
+
+  #[test]
+  fn test_library_size() -> Result<(), Box<dyn std::error::Error>> {
+    let library: MojangLibrary = serde_json::from_str(
+      r#"{ "name": "org.ow2.asm:asm:9.5", "downloads": { "artifact": { "path": "a.jar", "size": 122004 } } }"#
+    )?;
+    assert_eq!(library.get_size(), Some(122004));
+    let library: MojangLibrary = serde_json::from_str(r#"{ "name": "org.ow2.asm:asm:9.5", "downloads": {} }"#)?;
+    assert_eq!(library.get_size(), None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_version_file_sizes() -> Result<(), Box<dyn std::error::Error>> {
+    let version: ForgeVersionFileV2 = serde_json::from_str(
+      r#"{
+        "id": "1.20.1",
+        "time": "2023-06-12T13:25:51+00:00",
+        "releaseTime": "2023-06-12T13:25:51+00:00",
+        "type": "release",
+        "mainClass": "net.minecraft.client.main.Main",
+        "downloads": {
+          "client": { "sha1": "0c3ec587af28e5a785c0b4a7b8a30f9a8f78f838", "size": 23028853, "url": "https://piston-data.mojang.com/client.jar" }
+        },
+        "libraries": [
+          {
+            "name": "com.mojang:minecraft:1.20.1",
+            "downloads": { "artifact": { "path": "a.jar", "size": 5000000000 } }
+          }
+        ]
+      }"#
+    )?;
+    assert_eq!(version.downloads["client"].size, 23028853);
+    assert_eq!(version.libraries[0].get_size(), Some(5000000000));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_additional_library_dirs() -> Result<(), Box<dyn std::error::Error>> {
+    let root = TestDir::new("forge_additional_library_dirs");
+    let (libraries_dir, shared_dir) = (root.join("libraries"), root.join("shared"));
+
+    let artifact = Artifact::try_from("org.ow2.asm:asm:9.5".to_string())?;
+    let shared = artifact.get_local_path(&shared_dir);
+    fs::create_dir_all(shared.parent().unwrap())?;
+    fs::write(&shared, b"asm")?;
+    let sha1 = Sha1Sum::from_reader(&mut Cursor::new(b"asm"))?;
+    let library: MojangLibrary = serde_json::from_value(
+      serde_json::json!({
+        "name": "org.ow2.asm:asm:9.5",
+        "downloads": { "artifact": { "path": "org/ow2/asm/asm/9.5/asm-9.5.jar", "url": "https://invalid/asm-9.5.jar", "sha1": sha1.to_string() } }
+      })
+    )?;
+
+    let mut archive = empty_installer();
+    let mut grabbed = std::collections::HashSet::new();
+    let additional_dirs = vec![&shared_dir];
+    let pending = download_utils::resolve_library(&mut archive, &library, &libraries_dir, &|_| true, &mut grabbed, &additional_dirs, &download_utils::DownloadConfig::default()).await?;
+    assert!(pending.is_empty());
+    assert!(grabbed.contains(&artifact));
+    assert_eq!(fs::read(artifact.get_local_path(&libraries_dir))?, b"asm");
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_offline_library() -> Result<(), Box<dyn std::error::Error>> {
+    let root = TestDir::new("forge_offline_library");
+    let (libraries_dir, shared_dir) = (root.join("libraries"), root.join("shared"));
+    let sha1 = Sha1Sum::from_reader(&mut Cursor::new(b"asm"))?;
+    let library: MojangLibrary = serde_json::from_value(
+      serde_json::json!({
+        "name": "org.ow2.asm:asm:9.5",
+        "downloads": { "artifact": { "path": "org/ow2/asm/asm/9.5/asm-9.5.jar", "url": "https://invalid/asm-9.5.jar", "sha1": sha1.to_string() } }
+      })
+    )?;
+    let mut archive = empty_installer();
+    let config = download_utils::DownloadConfig { offline: true, ..Default::default() };
+    let additional_dirs = vec![&shared_dir];
+
+    let mut grabbed = std::collections::HashSet::new();
+    let result = download_utils::download_library(&mut archive, &library, &libraries_dir, &|_| true, &mut grabbed, &additional_dirs, &config).await;
+    let err = result.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(forge_client_install::ForgeInstallError::OfflineMissing(descriptor)) if descriptor == "org.ow2.asm:asm:9.5"));
+
+    let shared = library.name.get_local_path(&shared_dir);
+    fs::create_dir_all(shared.parent().unwrap())?;
+    fs::write(&shared, b"asm")?;
+    let downloaded = download_utils::download_library(&mut archive, &library, &libraries_dir, &|_| true, &mut grabbed, &additional_dirs, &config).await?;
+    assert!(downloaded.is_empty());
+    assert!(grabbed.contains(&library.name));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_offline_install() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TestDir::new("forge_offline_install");
+    let (installer_dir, mc_dir) = (dir.join("installer"), dir.join("minecraft"));
+    fs::create_dir_all(&installer_dir)?;
+    fs::write(installer_dir.join("install_profile.json"), profile_json(serde_json::json!({})))?;
+    fs::write(
+      installer_dir.join("version.json"),
+      r#"{
+        "id": "1.20.1-forge-47.2.0", "inheritsFrom": "1.20.1", "type": "release", "mainClass": "a.Main",
+        "libraries": [
+          { "name": "org.ow2.asm:asm:9.5", "downloads": { "artifact": { "path": "org/ow2/asm/asm/9.5/asm-9.5.jar", "url": "https://invalid/asm-9.5.jar" } } }
+        ]
+      }"#
+    )?;
+    let client_jar = mc_dir.join("versions").join("1.20.1").join("1.20.1.jar");
+    fs::create_dir_all(client_jar.parent().unwrap())?;
+    fs::write(&client_jar, b"client")?;
+
+    // The vanilla jar is there, so it's the library that can't be found
+    let mut install = forge_client_install::ForgeClientInstallBuilder::new().offline(true).build_from_dir(installer_dir)?;
+    let err = install.install_forge(&mc_dir, |_| true).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(forge_client_install::ForgeInstallError::OfflineMissing(descriptor)) if descriptor == "org.ow2.asm:asm:9.5"));
+
+    // Skipping the vanilla downloads, the missing vanilla json isn't needed either
+    install.set_skip_vanilla(true);
+    let progress = download_utils::ProgressCallback::new(|_, _, _| {});
+    let err = install.install_with_vanilla(&mc_dir, progress).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(forge_client_install::ForgeInstallError::OfflineMissing(descriptor)) if descriptor == "org.ow2.asm:asm:9.5"));
+    install.set_skip_vanilla(false);
+
+    // Flattening needs the vanilla json, a bad one fails the install instead of being downloaded again
+    let vanilla_json = client_jar.with_extension("json");
+    fs::write(&vanilla_json, r#"{ "id": "1.19.4" }"#)?;
+    install.set_keep_inherits_from(false);
+    let err = install.install_forge(&mc_dir, |_| true).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(forge_client_install::ForgeInstallError::InvalidProfile(_))));
+    assert_eq!(fs::read(&vanilla_json)?, br#"{ "id": "1.19.4" }"#);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_transactional_rollback() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TestDir::new("forge_transactional_rollback");
+    let (installer_dir, mc_dir) = (dir.join("installer"), dir.join("minecraft"));
+    fs::create_dir_all(&installer_dir)?;
+    fs::write(
+      installer_dir.join("install_profile.json"),
+      r#"{
+        "install": {
+          "profileName": "Forge", "target": "1.6.4-Forge9.11.1.1345", "path": "net.minecraftforge:minecraftforge:9.11.1.1345",
+          "version": "Minecraft Forge 9.11.1.1345", "filePath": "minecraftforge-universal-1.6.4-9.11.1.1345.jar",
+          "welcome": "Welcome", "minecraft": "1.6.4", "logo": "/big_logo.png", "mirrorList": ""
+        },
+        "versionInfo": {
+          "id": "1.6.4-Forge9.11.1.1345", "type": "release", "minecraftArguments": "", "mainClass": "net.minecraft.launchwrapper.Launch",
+          "libraries": []
+        }
+      }"#
+    )?;
+    let client_jar = mc_dir.join("versions").join("1.6.4").join("1.6.4.jar");
+    fs::create_dir_all(client_jar.parent().unwrap())?;
+    fs::write(&client_jar, b"client")?;
+    let mod_list = mc_dir.join("mods").join("mod_list.json");
+    fs::create_dir_all(mod_list.parent().unwrap())?;
+    fs::write(&mod_list, b"{}")?;
+
+    // The universal jar isn't in the installer, so the install fails after writing the version folder
+    let mut install = forge_client_install::ForgeClientInstallBuilder::new().offline(true).build_from_dir(installer_dir)?;
+    assert!(install.install_forge_transactional(&mc_dir, |_| true).await.is_err());
+    assert!(!install.get_created_files().contains(&mod_list));
+    assert_eq!(fs::read(&mod_list)?, b"{}");
+    let version_dir = mc_dir.join("versions").join("1.6.4-Forge9.11.1.1345");
+    assert!(!version_dir.exists());
+    assert!(client_jar.is_file());
+
+    // A version folder that was already there is being repaired, so it survives the rollback
+    fs::create_dir_all(&version_dir)?;
+    fs::write(version_dir.join("options.txt"), b"keep")?;
+    assert!(install.install_forge_transactional(&mc_dir, |_| true).await.is_err());
+    assert_eq!(fs::read(version_dir.join("options.txt"))?, b"keep");
+    // Released once the rollback is done
+    drop(forge_client_install::InstallLock::acquire(&mc_dir)?);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_url_overrides() -> Result<(), Box<dyn std::error::Error>> {
+    use forge_installer_profile::v1::ForgeLibrary;
+    let libraries: Vec<ForgeLibrary> = serde_json::from_value(
+      serde_json::json!([
+        { "name": "a.b:moved:1", "url": "https://old.example/", "clientreq": true },
+        { "name": "a.b:kept:1", "url": "https://old.example/", "clientreq": true }
+      ])
+    )?;
+    let mut config = download_utils::DownloadConfig::default();
+    config.url_overrides.insert("a.b:moved:1".to_string(), "https://new.example/moved-1.jar".to_string());
+    let mut source = empty_installer();
+    let root = TestDir::new("forge_url_overrides");
+    let urls = download_utils::plan_installed_libraries(true, &root, &libraries, &mut source, &config).await?
+      .into_iter()
+      .filter_map(|action| match action {
+        forge_client_install::PlannedAction::Download { url, .. } => url,
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(urls, vec!["https://new.example/moved-1.jar".to_string(), "https://old.example/a/b/kept/1/kept-1.jar".to_string()]);
+
+    let download = forge_installer_profile::v2::MojangArtifact::new("a/b/moved/1/moved-1.jar".to_string());
+    let overridden = config.override_download(&libraries[0].name, download);
+    assert_eq!(overridden.url.as_deref(), Some("https://new.example/moved-1.jar"));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_v1_library_outcomes() -> Result<(), Box<dyn std::error::Error>> {
+    use download_utils::{ LibraryCallback, LibraryOutcome };
+    use forge_installer_profile::v1::ForgeLibrary;
+    let root = TestDir::new("forge_v1_library_outcomes");
+    let valid_sha1 = Sha1Sum::from_reader(&mut Cursor::new(b"valid"))?;
+    let libraries: Vec<ForgeLibrary> = serde_json::from_value(
+      serde_json::json!([
+        { "name": "a.b:valid:1", "clientreq": true, "checksums": [valid_sha1.to_string()] },
+        { "name": "a.b:packed:1", "url": "https://invalid/", "clientreq": true },
+        { "name": "a.b:server:1", "serverreq": true },
+        { "name": "a.b:missing:1", "url": "https://invalid/", "clientreq": true }
+      ])
+    )?;
+    let valid = libraries[0].name.get_local_path(&root);
+    fs::create_dir_all(valid.parent().unwrap())?;
+    fs::write(&valid, b"valid")?;
+    let mut installer = Cursor::new(vec![]);
+    let mut writer = zip::ZipWriter::new(&mut installer);
+    writer.start_file(libraries[1].name.get_path_string(), zip::write::FileOptions::default())?;
+    writer.write_all(b"packed")?;
+    writer.finish()?;
+    drop(writer);
+
+    let outcomes = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let recorded = outcomes.clone();
+    let on_library = LibraryCallback::new(move |artifact, progress, total, outcome| {
+      recorded.lock().unwrap().push((artifact.get_descriptor(), progress, total, outcome));
+    });
+    // Offline, so the packed library can only come from the installer
+    let config = download_utils::DownloadConfig { offline: true, on_library: Some(on_library), ..Default::default() };
+    let mut grabbed = std::collections::HashSet::new();
+    let mut source = ForgeInstallProfileSource::Zip(ZipArchive::new(installer)?);
+    let cancel = forge_client_install::CancellationToken::new();
+    let err = download_utils::download_installed_libraries(true, &root, &libraries, &mut grabbed, &mut source, &config, &cancel).await.unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(forge_client_install::ForgeInstallError::OfflineMissing(descriptor)) if descriptor == "a.b:missing:1"));
+    assert_eq!(*outcomes.lock().unwrap(), vec![
+      ("a.b:valid:1".to_string(), 1, 4, LibraryOutcome::Valid),
+      ("a.b:packed:1".to_string(), 2, 4, LibraryOutcome::Grabbed),
+      ("a.b:server:1".to_string(), 3, 4, LibraryOutcome::Skipped)
+    ]);
+
+    // Found in an additional library directory, so it doesn't need the network
+    let shared_dir = root.join("shared");
+    let shared = libraries[3].name.get_local_path(&shared_dir);
+    fs::create_dir_all(shared.parent().unwrap())?;
+    fs::write(&shared, b"missing")?;
+    let shared_config = download_utils::DownloadConfig { additional_library_dirs: vec![shared_dir], ..config.clone() };
+    outcomes.lock().unwrap().clear();
+    download_utils::download_installed_libraries(true, &root, &libraries[3..].to_vec(), &mut grabbed, &mut source, &shared_config, &cancel).await?;
+    assert_eq!(outcomes.lock().unwrap()[0].3, LibraryOutcome::Grabbed);
+    assert_eq!(fs::read(libraries[3].name.get_local_path(&root))?, b"missing");
+
+    // Online, a library that can't be found anywhere fails the install
+    let mut config = download_utils::DownloadConfig { offline: false, ..config };
+    config.repositories.libraries_base = "https://mojang.invalid/".to_string();
+    let unreachable: Vec<ForgeLibrary> = serde_json::from_value(serde_json::json!([{ "name": "a.b:unreachable:1", "url": "https://invalid/", "clientreq": true }]))?;
+    outcomes.lock().unwrap().clear();
+    let (bad, _) = download_utils::download_installed_libraries(true, &root, &unreachable, &mut grabbed, &mut source, &config, &cancel).await?;
+    assert_eq!(outcomes.lock().unwrap()[0].3, LibraryOutcome::Bad);
+    assert_eq!(bad, vec![unreachable[0].name.clone()]);
+
+    // Without a url it's from the Mojang repository, left for the launcher unless that's fatal
+    let mojang: Vec<ForgeLibrary> = serde_json::from_value(serde_json::json!([{ "name": "a.b:mojang:1", "clientreq": true }]))?;
+    outcomes.lock().unwrap().clear();
+    let (bad, _) = download_utils::download_installed_libraries(true, &root, &mojang, &mut grabbed, &mut source, &config, &cancel).await?;
+    assert_eq!(outcomes.lock().unwrap()[0].3, LibraryOutcome::Unavailable);
+    assert!(bad.is_empty());
+    // Offline it's the same, the launcher can still fetch it later
+    let offline = download_utils::DownloadConfig { offline: true, ..config.clone() };
+    let (bad, _) = download_utils::download_installed_libraries(true, &root, &mojang, &mut grabbed, &mut source, &offline, &cancel).await?;
+    assert_eq!(outcomes.lock().unwrap()[1].3, LibraryOutcome::Unavailable);
+    assert!(bad.is_empty());
+    outcomes.lock().unwrap().clear();
+    let config = download_utils::DownloadConfig { treat_unmirrored_mojang_failure_as_fatal: true, ..config };
+    let (bad, _) = download_utils::download_installed_libraries(true, &root, &mojang, &mut grabbed, &mut source, &config, &cancel).await?;
+    assert_eq!(outcomes.lock().unwrap()[0].3, LibraryOutcome::Bad);
+    assert_eq!(bad, vec![mojang[0].name.clone()]);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_gzip_download() -> Result<(), Box<dyn std::error::Error>> {
+    use tokio::{ io::{ AsyncReadExt, AsyncWriteExt }, net::TcpListener };
+    // gzip of "gzipped library"
+    const GZIPPED: [u8; 35] = [
+      31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 75, 175, 202, 44, 40, 72, 77, 81, 200, 201, 76, 42, 74, 44, 170, 4, 0, 121, 25, 97, 150, 15, 0, 0, 0,
+    ];
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/library.jar", listener.local_addr()?);
+    let server = tokio::spawn(async move {
+      let (mut socket, _) = listener.accept().await?;
+      let mut request = vec![0; 4096];
+      let _ = socket.read(&mut request).await?;
+      let headers = format!("HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", GZIPPED.len());
+      socket.write_all(headers.as_bytes()).await?;
+      socket.write_all(&GZIPPED).await?;
+      socket.shutdown().await
+    });
+
+    let dir = TestDir::new("forge_gzip_download");
+    let target = dir.join("library.jar");
+    let checksums = vec![Checksum::from(Sha1Sum::from_reader(&mut Cursor::new(b"gzipped library"))?)];
+    let progress = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let recorded = progress.clone();
+    let on_progress = download_utils::ProgressCallback::new(move |_, transferred, total| recorded.lock().unwrap().push((transferred, total)));
+    let config = download_utils::DownloadConfig { on_progress: Some(on_progress), ..Default::default() };
+    download_utils::download_file(&target, &url, &checksums, Some(15), &config).await?;
+    server.await??;
+    assert_eq!(fs::read(&target)?, b"gzipped library");
+    // The decoded body has no Content-Length, the declared size is reported instead
+    assert_eq!(progress.lock().unwrap().last(), Some(&(15, Some(15))));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_provided_library() -> Result<(), Box<dyn std::error::Error>> {
+    // The forge jar is built by the processors, so it has no url
+    let library: MojangLibrary = serde_json::from_value(
+      serde_json::json!({
+        "name": "net.minecraftforge:forge:1.20.1-47.2.0:client",
+        "downloads": { "artifact": { "path": "net/minecraftforge/forge/1.20.1-47.2.0/forge-1.20.1-47.2.0-client.jar", "url": "" } }
+      })
+    )?;
+    let libraries_dir = TestDir::new("forge_provided_library");
+    let mut archive = empty_installer();
+    let mut grabbed = std::collections::HashSet::new();
+    let config = download_utils::DownloadConfig::default();
+    let downloaded = download_utils::download_library(&mut archive, &library, &libraries_dir, &|_| true, &mut grabbed, &vec![], &config).await?;
+    assert!(downloaded.is_empty() && grabbed.is_empty());
+    assert!(download_utils::plan_library(&mut archive, &library, &libraries_dir, &|_| true, &vec![], &config).await?.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_sha1_from_reader() -> Result<(), Box<dyn std::error::Error>> {
+    let empty = Sha1Sum::from_reader(&mut Cursor::new(vec![]))?;
+    assert_eq!(empty.to_string(), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+    // Bigger than one chunk
+    let data = vec![b'a'; 200 * 1024];
+    let mut hasher = Sha1::new();
+    hasher.update(&data);
+    assert_eq!(Sha1Sum::from_reader(&mut Cursor::new(data))?, Sha1Sum::new(hasher.finalize().into()));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_verify_libraries() -> Result<(), Box<dyn std::error::Error>> {
+    let libraries_dir = TestDir::new("forge_verify_libraries");
+    let artifact = |descriptor: &str| Artifact::try_from(descriptor.to_string()).unwrap();
+    let (valid, corrupt, unchecked, missing) = (artifact("a:valid:1"), artifact("a:corrupt:1"), artifact("a:unchecked:1"), artifact("a:missing:1"));
+    for library in [&valid, &corrupt, &unchecked] {
+      let path = library.get_local_path(&libraries_dir);
+      fs::create_dir_all(path.parent().unwrap())?;
+      fs::write(path, b"library")?;
+    }
+    let sum = Sha1Sum::from_reader(&mut Cursor::new(b"library"))?;
+    let other = Sha1Sum::from_reader(&mut Cursor::new(b"other"))?;
+    let expected = HashMap::from([(valid.clone(), sum.clone()), (corrupt.clone(), other.clone()), (missing.clone(), sum)]);
+
+    let mut bad = download_utils::verify_libraries(&[valid, corrupt.clone(), unchecked, missing.clone()], &libraries_dir, &expected).await;
+    bad.sort_by_key(|artifact| artifact.get_descriptor());
+    assert_eq!(bad, vec![corrupt, missing]);
+    Ok(())
+  }
+
+  #[test]
+  fn test_checksum_verify() -> Result<(), Box<dyn std::error::Error>> {
+    let sha256 = Checksum::from_reader_with(ChecksumAlgorithm::Sha256, &mut Cursor::new(b"abc"))?;
+    assert_eq!(sha256.to_string(), "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert!(sha256.verify(&mut Cursor::new(b"abc")));
+    assert!(!sha256.verify(&mut Cursor::new(b"abd")));
+    let sha1 = Checksum::from_reader_with(ChecksumAlgorithm::Sha1, &mut Cursor::new(b"abc"))?;
+    assert_eq!(Checksum::get_strongest_algorithm(std::slice::from_ref(&sha1)), Some(ChecksumAlgorithm::Sha1));
+    assert_eq!(Checksum::get_strongest_algorithm(&[sha1, sha256]), Some(ChecksumAlgorithm::Sha256));
+
+    let dir = TestDir::new("forge_verify_file");
+    fs::create_dir_all(&*dir)?;
+    let path = dir.join("file.txt");
+    fs::write(&path, b"abc")?;
+    let expected = Sha1Sum::from_reader(&mut Cursor::new(b"abc"))?;
+    assert_eq!(Sha1Sum::from_file(&path)?, expected);
+    assert!(Sha1Sum::verify_file(&path, &expected)?);
+    assert!(!Sha1Sum::verify_file(&path, &Sha1Sum::from_reader(&mut Cursor::new(b"abd"))?)?);
+    fs::remove_file(&path)?;
+    assert!(Sha1Sum::verify_file(&path, &expected).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_artifact_eq() -> Result<(), Box<dyn std::error::Error>> {
+    let jar = Artifact::try_from("net.minecraftforge:forge:1.20.1-47.2.0".to_string())?;
+    let explicit_jar = Artifact::try_from("net.minecraftforge:forge:1.20.1-47.2.0@jar".to_string())?;
+    let universal = Artifact::try_from("net.minecraftforge:forge:1.20.1-47.2.0:universal".to_string())?;
+    assert_eq!(jar, explicit_jar);
+    assert_ne!(jar, universal);
+    let set: std::collections::HashSet<Artifact> = [jar, explicit_jar, universal].into_iter().collect();
+    assert_eq!(set.len(), 2);
+    Ok(())
+  }
+
+  #[test]
+  fn test_artifact_parse() {
+    let artifact: Artifact = "net.minecraftforge:forge:1.20.1-47.2.0".parse().unwrap();
+    assert_eq!(artifact.group_id, vec!["net", "minecraftforge"]);
+    assert_eq!(artifact.artifact_id, "forge");
+    assert_eq!(artifact.version, "1.20.1-47.2.0");
+    assert_eq!(artifact.classifier, None);
+    assert_eq!(artifact.ext, "jar");
+
+    let artifact: Artifact = "net.minecraftforge:forge:1.20.1-47.2.0:installer".parse().unwrap();
+    assert_eq!(artifact.classifier.as_deref(), Some("installer"));
+    assert_eq!(artifact.get_file(), "forge-1.20.1-47.2.0-installer.jar");
+
+    let artifact: Artifact = "de.oceanlabs.mcp:mcp_config:1.20.1-20230612.114412@zip".parse().unwrap();
+    assert_eq!(artifact.ext, "zip");
+    assert_eq!(artifact.get_file(), "mcp_config-1.20.1-20230612.114412.zip");
+  }
+
+  #[test]
+  fn test_artifact_round_trip() {
+    let artifact = Artifact {
+      original_descriptor: None,
+      group_id: vec!["foo".to_string()],
+      artifact_id: "bar".to_string(),
+      version: "1.0".to_string(),
+      classifier: Some("installer".to_string()),
+      ext: "zip".to_string(),
+    };
+    assert_eq!(artifact.get_descriptor(), "foo:bar:1.0:installer@zip");
+    let parsed = Artifact::try_from(artifact.to_string()).unwrap();
+    assert_eq!(parsed, artifact);
+    assert_eq!(parsed.ext, "zip");
+
+    let json = serde_json::to_string(&artifact).unwrap();
+    assert_eq!(json, r#""foo:bar:1.0:installer@zip""#);
+    assert_eq!(serde_json::from_str::<Artifact>(&json).unwrap(), artifact);
+
+    let jar = Artifact { classifier: None, ext: "jar".to_string(), ..artifact };
+    assert_eq!(jar.get_descriptor(), "foo:bar:1.0");
+  }
+
+  #[test]
+  fn test_artifact_with() -> Result<(), Box<dyn std::error::Error>> {
+    // Written with an explicit @jar, the derived artifacts don't keep that descriptor
+    let forge = Artifact::try_from("net.minecraftforge:forge:1.20.1-47.1.0@jar".to_string())?;
+    let installer = forge.with_classifier(Some("installer"));
+    assert_eq!(installer.get_descriptor(), "net.minecraftforge:forge:1.20.1-47.1.0:installer");
+    assert_eq!(installer.get_path_string(), "net/minecraftforge/forge/1.20.1-47.1.0/forge-1.20.1-47.1.0-installer.jar");
+    assert_eq!(installer.with_classifier(None), forge);
+
+    let sources = forge.with_classifier(Some("sources")).with_ext("zip");
+    assert_eq!(sources.get_descriptor(), "net.minecraftforge:forge:1.20.1-47.1.0:sources@zip");
+    assert_eq!(sources.get_file(), "forge-1.20.1-47.1.0-sources.zip");
+    assert_eq!(forge.with_ext("jar").get_descriptor(), "net.minecraftforge:forge:1.20.1-47.1.0");
+    Ok(())
+  }
+
+  #[test]
+  fn test_artifact_from_path() -> Result<(), Box<dyn std::error::Error>> {
+    let universal = Artifact::from_path("net/minecraftforge/forge/1.20.1-47.1.0/forge-1.20.1-47.1.0-universal.jar").ok_or("not an artifact")?;
+    assert_eq!(universal.get_descriptor(), "net.minecraftforge:forge:1.20.1-47.1.0:universal");
+    let client = Artifact::from_path("net/minecraft/client/1.20.1/client-1.20.1.txt").ok_or("not an artifact")?;
+    assert_eq!(client.get_descriptor(), "net.minecraft:client:1.20.1@txt");
+    assert!(Artifact::from_path("net/minecraftforge/forge/maven-metadata.xml").is_none());
+    assert!(Artifact::from_path("forge/1.0/forge-1.0.jar").is_none());
+
+    let natives = Artifact::try_from("org.lwjgl:lwjgl:3.3.1:natives-windows".to_string())?;
+    assert_eq!(Artifact::from_relative_path(&natives.get_local_path(&PathBuf::new()))?, natives);
+    assert_eq!(Artifact::from_relative_path(Path::new(&natives.get_path_string()))?.get_path_string(), natives.get_path_string());
+    assert!(Artifact::from_relative_path(Path::new("org/lwjgl/lwjgl/3.3.1/other-3.3.1.jar")).is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_artifact_parse_errors() {
+    assert!(matches!("net.minecraftforge:forge".parse::<Artifact>(), Err(ArtifactParseError::TooFewParts(_))));
+    assert!(matches!(":forge:1.0".parse::<Artifact>(), Err(ArtifactParseError::EmptyGroup(_))));
+    assert!(matches!("net.minecraftforge::1.0".parse::<Artifact>(), Err(ArtifactParseError::EmptyArtifactId(_))));
+    assert!(matches!("net.minecraftforge:forge:".parse::<Artifact>(), Err(ArtifactParseError::EmptyVersion(_))));
+    let five_parts = "net.minecraftforge:forge:1.20.1-47.1.0:installer:extra".parse::<Artifact>();
+    assert!(matches!(five_parts, Err(ArtifactParseError::TooManyParts(_))));
+  }
+
+  #[test]
+  fn test_artifact_parts() -> Result<(), Box<dyn std::error::Error>> {
+    let forge = "net.minecraftforge:forge:1.20.1-47.1.0".parse::<Artifact>()?;
+    assert_eq!((forge.version.as_str(), forge.classifier.as_deref(), forge.ext.as_str()), ("1.20.1-47.1.0", None, "jar"));
+    let installer = "net.minecraftforge:forge:1.20.1-47.1.0:installer@zip".parse::<Artifact>()?;
+    assert_eq!((installer.version.as_str(), installer.classifier.as_deref(), installer.ext.as_str()), ("1.20.1-47.1.0", Some("installer"), "zip"));
+    assert_eq!(installer.get_file(), "forge-1.20.1-47.1.0-installer.zip");
+    Ok(())
+  }
+
+  // Trimmed down install_profile.json files from legacy installers
+  const LEGACY_PROFILE_1_5_2: &str =
+    r#"{
+    "install": {
+      "target": "1.5.2-Forge7.8.1.738",
+      "path": "net.minecraftforge:minecraftforge:7.8.1.738",
+      "minecraft": "1.5.2"
+    },
+    "versionInfo": {
+      "id": "1.5.2-Forge7.8.1.738",
+      "type": "release",
+      "minecraftArguments": "--username ${auth_player_name} --session ${auth_session} --version ${version_name} --gameDir ${game_directory} --assetsDir ${game_assets}",
+      "mainClass": "net.minecraft.launchwrapper.Launch",
+      "libraries": [
+        { "name": "net.minecraftforge:minecraftforge:7.8.1.738", "url": "http://files.minecraftforge.net/maven/" },
+        { "name": "net.minecraft:launchwrapper:1.3", "serverreq": true, "clientreq": true },
+        { "name": "org.ow2.asm:asm-all:4.1", "serverreq": true, "clientreq": true }
+      ]
+    }
+  }"#;
+
+  const LEGACY_PROFILE_1_6_4: &str =
+    r#"{
+    "install": {
+      "profileName": "Forge",
+      "target": "1.6.4-Forge9.11.1.1345",
+      "path": "net.minecraftforge:minecraftforge:9.11.1.1345",
+      "version": "Minecraft Forge 9.11.1.1345",
+      "filePath": "minecraftforge-universal-1.6.4-9.11.1.1345.jar",
+      "welcome": "Welcome to the simple Forge installer.",
+      "minecraft": "1.6.4",
+      "logo": "/big_logo.png",
+      "mirrorList": "http://files.minecraftforge.net/mirror-brand.list"
+    },
+    "versionInfo": {
+      "id": "1.6.4-Forge9.11.1.1345",
+      "time": "2014-03-07T14:45:31-0500",
+      "releaseTime": "1960-01-01T00:00:00-0700",
+      "type": "release",
+      "minecraftArguments": "--username ${auth_player_name} --session ${auth_session} --version ${version_name} --gameDir ${game_directory} --assetsDir ${game_assets} --tweakClass cpw.mods.fml.common.launcher.FMLTweaker",
+      "mainClass": "net.minecraft.launchwrapper.Launch",
+      "minimumLauncherVersion": 4,
+      "libraries": [
+        { "name": "net.minecraftforge:minecraftforge:9.11.1.1345", "url": "http://files.minecraftforge.net/maven/" },
+        { "name": "net.minecraft:launchwrapper:1.8", "serverreq": true },
+        {
+          "name": "org.scala-lang:scala-library:2.10.2",
+          "url": "http://files.minecraftforge.net/maven/",
+          "checksums": ["dd235d04037dc6f4b6090257872dd35359a563ce"],
+          "serverreq": true,
+          "clientreq": true
+        }
+      ]
+    }
+  }"#;
+
+  #[test]
+  fn test_legacy_profiles() {
+    let profile = ForgeInstallerProfile::from_reader(Cursor::new(LEGACY_PROFILE_1_5_2)).unwrap();
+    let ForgeInstallerProfile::V1(profile) = profile else { panic!("1.5.2 profile should be V1") };
+    assert_eq!(profile.install.get_file_path(), "minecraftforge-universal-1.5.2-7.8.1.738.jar");
+    assert_eq!(profile.get_libraries("clientreq", |_| true).len(), 2);
+
+    let profile = ForgeInstallerProfile::from_reader(Cursor::new(LEGACY_PROFILE_1_6_4)).unwrap();
+    let ForgeInstallerProfile::V1(profile) = profile else { panic!("1.6.4 profile should be V1") };
+    assert_eq!(profile.install.get_file_path(), "minecraftforge-universal-1.6.4-9.11.1.1345.jar");
+    assert_eq!(profile.install.mirror_list.as_deref(), Some("http://files.minecraftforge.net/mirror-brand.list"));
+    assert_eq!(profile.get_libraries("clientreq", |_| true).len(), 1);
+  }
+
+  #[test]
+  fn test_optionals() {
+    let profile: ForgeInstallerProfileV1 = serde_json::from_str(LEGACY_PROFILE_1_6_4).unwrap();
+    let optional = |name: &str, client: bool, server: bool| ForgeOptional {
+      name: name.to_string(),
+      client,
+      server,
+      default: true,
+      inject: true,
+      desc: String::new(),
+      url: String::new(),
+      artifact: Artifact::try_from(format!("com.example:{name}:1.0")).unwrap(),
+      maven: String::new(),
+    };
+    let profile = ForgeInstallerProfileV1 { optionals: vec![optional("both", true, true), optional("server", false, true)], ..profile };
+    assert_eq!(profile.get_optionals("client").len(), 1);
+    assert_eq!(profile.get_optionals("server").len(), 2);
+
+    let selected = std::collections::HashSet::from(["com.example:both:1.0".to_string()]);
+    let libraries = profile.get_libraries("clientreq", |descriptor| selected.contains(descriptor));
+    let enabled = libraries.iter().filter(|lib| lib.name.get_descriptor().starts_with("com.example:") && lib.enabled).collect::<Vec<_>>();
+    assert_eq!(enabled.len(), 1);
+    assert_eq!(enabled[0].name.get_descriptor(), "com.example:both:1.0");
+  }
+
+  #[test]
+  fn test_version_arguments() -> Result<(), Box<dyn std::error::Error>> {
+    let version = |arguments: serde_json::Value| -> Result<forge_installer_profile::ForgeVersionInfo, serde_json::Error> {
+      let mut json = serde_json::json!({ "id": "forge", "type": "release", "mainClass": "a.Main" });
+      json.as_object_mut().unwrap().extend(arguments.as_object().unwrap().clone());
+      serde_json::from_value(json)
+    };
+    let legacy = version(serde_json::json!({ "minecraftArguments": "--username ${auth_player_name}  --tweakClass cpw.mods.fml.common.launcher.FMLTweaker" }))?;
+    assert_eq!(legacy.get_game_arguments(), vec!["--username", "${auth_player_name}", "--tweakClass", "cpw.mods.fml.common.launcher.FMLTweaker"]);
+    assert!(legacy.get_jvm_arguments().is_empty());
+
+    let modern = version(
+      serde_json::json!({
+        "arguments": {
+          "game": ["--launchTarget", "forgeclient", { "rules": [{ "action": "allow", "features": { "is_demo_user": true } }], "value": "--demo" }],
+          "jvm": ["-Dforgeclient=true", { "rules": [{ "action": "allow", "os": { "name": "osx" } }], "value": ["-XstartOnFirstThread"] }]
+        }
+      })
+    )?;
+    assert_eq!(modern.get_game_arguments(), vec!["--launchTarget", "forgeclient"]);
+    assert_eq!(modern.get_jvm_arguments(), vec!["-Dforgeclient=true"]);
+    Ok(())
+  }
+
+  #[test]
+  fn test_flatten_version_json() -> Result<(), Box<dyn std::error::Error>> {
+    let forge: forge_installer_profile::ForgeVersionInfo = serde_json::from_value(
+      serde_json::json!({
+        "id": "1.20.1-forge-47.2.0",
+        "inheritsFrom": "1.20.1",
+        "type": "release",
+        "mainClass": "cpw.mods.bootstraplauncher.BootstrapLauncher",
+        "libraries": [{ "name": "org.ow2.asm:asm:9.5", "downloads": {} }],
+        "arguments": { "game": ["--launchTarget", "forgeclient"] }
+      })
+    )?;
+    let vanilla = serde_json::json!({
+      "id": "1.20.1",
+      "type": "release",
+      "mainClass": "net.minecraft.client.main.Main",
+      "assetIndex": { "id": "5" },
+      "libraries": [{ "name": "org.ow2.asm:asm:9.3" }, { "name": "com.mojang:brigadier:1.1.8" }],
+      "arguments": { "game": ["--username", "${auth_player_name}"], "jvm": ["-Xss1M"] }
+    });
+    let flat = forge.flatten(&vanilla)?;
+    assert_eq!(flat["id"], "1.20.1-forge-47.2.0");
+    assert_eq!(flat["mainClass"], "cpw.mods.bootstraplauncher.BootstrapLauncher");
+    assert_eq!(flat["jar"], "1.20.1");
+    assert_eq!(flat["assetIndex"]["id"], "5");
+    assert!(flat.get("inheritsFrom").is_none());
+    let libraries = flat["libraries"].as_array().unwrap().iter().map(|lib| lib["name"].as_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(libraries, vec!["org.ow2.asm:asm:9.5", "com.mojang:brigadier:1.1.8"]);
+    assert_eq!(flat["arguments"]["game"].as_array().unwrap().len(), 4);
+    assert_eq!(flat["arguments"]["jvm"].as_array().unwrap().len(), 1);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_server_side_data() -> Result<(), Box<dyn std::error::Error>> {
+    let profile = ForgeInstallerProfile::from_reader(
+      Cursor::new(
+        profile_json(serde_json::json!({
+          "data": {
+            "MOJMAPS": { "client": "[net.minecraft:client:1.20.1:mappings@txt]", "server": "" },
+            "MAPPINGS_SHA": { "client": "", "server": "0a3b8d4f2e9c7a1b6d5e4f3a2b1c0d9e8f7a6b5c" },
+            "BINPATCH": { "client": "/data/client.lzma", "server": "/data/server.lzma" }
+          }
+        }))
+      )
+    )?;
+    assert!(!profile.requires_processors());
+    let mut installer = Cursor::new(vec![]);
+    let mut writer = zip::ZipWriter::new(&mut installer);
+    writer.start_file("data/server.lzma", zip::write::FileOptions::default())?;
+    writer.write_all(b"patch")?;
+    writer.finish()?;
+    drop(writer);
+
+    let root = TestDir::new("forge_server_side_data");
+    let profile = std::sync::Arc::new(profile);
+    let mut processors = post_processors::PostProcessors::new(profile.clone(), false, PathBuf::from("java"))?;
+    processors.set_temp_dir(root.join("temp"));
+    let cancel = forge_client_install::CancellationToken::new();
+    assert_eq!(processors.get_task_count(), 0);
+    let outputs = processors.process(&root.join("libraries"), &root.join("server.jar"), &root, None, &mut ForgeInstallProfileSource::Zip(ZipArchive::new(installer.clone())?), &cancel).await?;
+    assert!(outputs.is_empty());
+    assert_eq!(processors.get_data()["MOJMAPS"], "");
+    assert_eq!(processors.get_data()["MAPPINGS_SHA"], "0a3b8d4f2e9c7a1b6d5e4f3a2b1c0d9e8f7a6b5c");
+    assert!(processors.get_data()["BINPATCH"].ends_with("server.lzma"));
+    assert!(!PathBuf::from(&processors.get_data()["BINPATCH"]).exists());
+
+    let mut processors = post_processors::PostProcessors::new(profile, false, PathBuf::from("java"))?;
+    processors.set_temp_dir(root.join("temp"));
+    processors.set_keep_temp_dir(true);
+    let source = &mut ForgeInstallProfileSource::Zip(ZipArchive::new(installer)?);
+    processors.process(&root.join("libraries"), &root.join("server.jar"), &root, None, source, &cancel).await?;
+    assert!(PathBuf::from(&processors.get_data()["BINPATCH"]).is_file());
+    Ok(())
+  }
+
+  #[test]
+  fn test_cached_processor_outputs() -> Result<(), Box<dyn std::error::Error>> {
+    let root = TestDir::new("forge_cached_processor_outputs");
+    fs::create_dir_all(&root)?;
+    let output = root.join("client-srg.jar");
+    fs::write(&output, b"patched")?;
+    let sha1 = Sha1Sum::from_reader(&mut Cursor::new(b"patched"))?;
+    let data = HashMap::from([
+      ("PATCHED".to_string(), output.to_str().unwrap().to_string()),
+      ("PATCHED_SHA".to_string(), sha1.to_string()),
+    ]);
+    let processor: forge_installer_profile::v2::Processor = serde_json::from_str(
+      r#"{ "jar": "net.minecraftforge:binarypatcher:1.1.1", "outputs": { "{PATCHED}": "{PATCHED_SHA}" } }"#
+    )?;
+    // Every output is up to date, so java isn't needed
+    let cancel = forge_client_install::CancellationToken::new();
+    let env = ProcessorEnv { java_path: &PathBuf::from("java"), jvm_options: &JvmOptions::default(), os: &TargetOs::current(), on_before: None };
+    let result = processor.process(&data, &root, &env, &cancel)?;
+    assert!(!result.ran);
+    assert_eq!(result.outputs, HashMap::from([(output, sha1)]));
+    Ok(())
+  }
+
+  #[test]
+  fn test_processor_output_without_checksum() -> Result<(), Box<dyn std::error::Error>> {
+    let root = TestDir::new("forge_processor_output_without_checksum");
+    fs::create_dir_all(&root)?;
+    let output = root.join("client-extra.jar");
+    let data = HashMap::from([("EXTRA".to_string(), output.to_str().unwrap().to_string())]);
+    let processor: forge_installer_profile::v2::Processor = serde_json::from_str(
+      r#"{ "jar": "net.minecraftforge:installertools:1.3.0", "outputs": { "{EXTRA}": null } }"#
+    )?;
+    let cancel = forge_client_install::CancellationToken::new();
+    let env = ProcessorEnv { java_path: &PathBuf::from("java"), jvm_options: &JvmOptions::default(), os: &TargetOs::current(), on_before: None };
+    // Missing, so the processor has to run and fails on its missing jar instead of panicking
+    let missing = processor.process(&data, &root, &env, &cancel);
+    assert!(missing.unwrap_err().to_string().contains("Missing Jar"));
+    // Existing, which is all that can be checked
+    fs::write(&output, b"extra")?;
+    let result = processor.process(&data, &root, &env, &cancel)?;
+    assert!(!result.ran);
+    assert!(result.outputs.is_empty());
+    Ok(())
+  }
+
+  #[test]
+  fn test_processor_hook() -> Result<(), Box<dyn std::error::Error>> {
+    use forge_installer_profile::v2::{ Processor, ProcessorDecision, ProcessorHook };
+    let root = TestDir::new("forge_processor_hook");
+    let processor: Processor = serde_json::from_str(r#"{ "jar": "net.minecraftforge:installertools:1.3.0", "args": ["--task", "{TASK}"] }"#)?;
+    let jar = processor.jar.get_local_path(&root);
+    fs::create_dir_all(jar.parent().unwrap())?;
+    let mut writer = zip::ZipWriter::new(File::create(&jar)?);
+    writer.start_file("META-INF/MANIFEST.MF", zip::write::FileOptions::default())?;
+    writer.write_all(b"Manifest-Version: 1.0\nMain-Class: net.minecraftforge.installertools.ConsoleTool\n")?;
+    writer.finish()?;
+    drop(writer);
+
+    let data = HashMap::from([("TASK".to_string(), "MCP_DATA".to_string())]);
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let recorded = seen.clone();
+    let skip = ProcessorHook::new(move |invocation| {
+      recorded.lock().unwrap().push(invocation.clone());
+      ProcessorDecision::Skip
+    });
+    let cancel = forge_client_install::CancellationToken::new();
+    // Neither decision runs java, so a missing binary is fine
+    let java = PathBuf::from("no-java");
+    let env = ProcessorEnv { java_path: &java, jvm_options: &JvmOptions::default(), os: &TargetOs::current(), on_before: Some(&skip) };
+    let skipped = processor.process(&data, &root, &env, &cancel)?;
+    assert!(!skipped.ran);
+    let invocation = seen.lock().unwrap()[0].clone();
+    assert_eq!(invocation.main_class, "net.minecraftforge.installertools.ConsoleTool");
+    assert_eq!(invocation.args, vec!["--task", "MCP_DATA"]);
+    assert_eq!(invocation.command.first().map(String::as_str), Some("no-java"));
+    assert_eq!(invocation.command.last().map(String::as_str), Some("MCP_DATA"));
+
+    let abort = ProcessorHook::new(|_| ProcessorDecision::Abort);
+    let aborted = processor.process(&data, &root, &ProcessorEnv { on_before: Some(&abort), ..env }, &cancel);
+    assert!(matches!(aborted.unwrap_err().downcast_ref(), Some(forge_client_install::ForgeInstallError::ProcessorRejected(_))));
+    Ok(())
+  }
+
+  #[test]
+  fn test_processor_checksums() -> Result<(), Box<dyn std::error::Error>> {
+    let profile = ForgeInstallerProfile::from_reader(
+      Cursor::new(
+        profile_json(serde_json::json!({
+          "libraries": [
+            { "name": "net.minecraftforge:binarypatcher:1.1.1", "downloads": { "artifact": { "path": "", "url": "", "sha1": "0a3b8d4f2e9c7a1b6d5e4f3a2b1c0d9e8f7a6b5c" } } },
+            { "name": "net.minecraftforge:srgutils:0.4.3", "downloads": { "artifact": { "path": "", "url": "" } } },
+            { "name": "org.ow2.asm:asm:9.5", "downloads": { "artifact": { "path": "", "url": "", "sha1": "1a3b8d4f2e9c7a1b6d5e4f3a2b1c0d9e8f7a6b5c" } } }
+          ],
+          "processors": [{ "jar": "net.minecraftforge:binarypatcher:1.1.1", "classpath": ["net.minecraftforge:srgutils:0.4.3"], "args": [] }]
+        }))
+      )
+    )?;
+    let processors = post_processors::PostProcessors::new(std::sync::Arc::new(profile), true, PathBuf::from("java"))?;
+    // asm isn't run and srgutils has no checksum
+    let patcher = Artifact::try_from("net.minecraftforge:binarypatcher:1.1.1".to_string())?;
+    let expected = Sha1Sum::try_from("0a3b8d4f2e9c7a1b6d5e4f3a2b1c0d9e8f7a6b5c".to_string())?;
+    assert_eq!(processors.get_processor_checksums(), HashMap::from([(patcher, expected)]));
+    Ok(())
+  }
+
+  #[test]
+  fn test_processor_libraries_arch() -> Result<(), Box<dyn std::error::Error>> {
+    let profile = ForgeInstallerProfile::from_reader(
+      Cursor::new(
+        profile_json(serde_json::json!({
+          "libraries": [
+            { "name": "a.b:plain:1", "downloads": {} },
+            { "name": "a.b:arm:1", "downloads": {}, "rules": [{ "action": "allow", "os": { "arch": "arm64" } }] }
+          ],
+          "processors": [{ "jar": "a.b:plain:1", "args": [] }]
+        }))
+      )
+    )?;
+    let mut processors = post_processors::PostProcessors::new(std::sync::Arc::new(profile), true, PathBuf::from("java"))?;
+    processors.set_target_arch(TargetArch::X86_64);
+    assert_eq!(processors.get_libraries().len(), 1);
+    processors.set_target_arch(TargetArch::Arm64);
+    assert_eq!(processors.get_libraries().len(), 2);
+    Ok(())
+  }
+
+  #[test]
+  fn test_plan_processors() -> Result<(), Box<dyn std::error::Error>> {
+    let profile = ForgeInstallerProfile::from_reader(
+      Cursor::new(
+        profile_json(serde_json::json!({
+          "processors": [{
+            "jar": "net.minecraftforge:binarypatcher:1.1.1", "classpath": ["net.minecraftforge:srgutils:0.4.3"],
+            "args": ["--patch", "{BINPATCH}", "--out", "[net.minecraft:client:1.20.1:srg]"]
+          }],
+          "data": { "BINPATCH": { "client": "/data/client.lzma", "server": "/data/server.lzma" } }
+        }))
+      )
+    )?;
+    assert!(profile.requires_processors());
+    let root = TestDir::new("forge_plan_processors");
+    let mut processors = post_processors::PostProcessors::new(std::sync::Arc::new(profile), true, PathBuf::from("java"))?;
+    processors.set_jvm_options(JvmOptions { args: vec!["-Xmx512m".to_string()], java_tool_options: None });
+    // No libraries, one processor and one data entry
+    assert_eq!(processors.get_task_count(), 2);
+    processors.set_temp_dir(root.join("temp"));
+    let libraries = root.join("libraries");
+    // Not in the installer, so it's passed to the processor as is
+    let actions = processors.plan(&libraries, &root.join("client.jar"), &root, None, &mut empty_installer())?;
+    assert!(!actions.iter().any(|action| matches!(action, PlannedAction::Extract { .. })));
+    let Some(PlannedAction::RunProcessor { command, .. }) = actions.last() else {
+      panic!("The processor doesn't run: {actions:?}");
+    };
+    assert_eq!(command[6], "/data/client.lzma");
+
+    let mut installer = Cursor::new(vec![]);
+    let mut writer = zip::ZipWriter::new(&mut installer);
+    writer.start_file("data/client.lzma", zip::write::FileOptions::default())?;
+    writer.finish()?;
+    drop(writer);
+    let mut source = ForgeInstallProfileSource::Zip(ZipArchive::new(installer)?);
+    let actions = processors.plan(&libraries, &root.join("client.jar"), &root, None, &mut source)?;
+    assert!(!root.exists());
+
+    let Some(PlannedAction::Extract { entry, target }) = actions.iter().find(|action| matches!(action, PlannedAction::Extract { .. })) else {
+      panic!("The patch isn't extracted: {actions:?}");
+    };
+    assert_eq!(entry, "/data/client.lzma");
+    let Some(PlannedAction::RunProcessor { command, .. }) = actions.last() else {
+      panic!("The processor doesn't run: {actions:?}");
+    };
+    assert_eq!(command[0], "java");
+    assert_eq!(command[1], "-Xmx512m");
+    assert_eq!(command[2], "-cp");
+    assert_eq!(command[4], "<Main-Class of net.minecraftforge:binarypatcher:1.1.1>");
+    assert_eq!(command[6], target.to_str().unwrap());
+    let output = Artifact::try_from("net.minecraft:client:1.20.1:srg".to_string())?.get_local_path(&libraries);
+    assert_eq!(command[8], output.to_str().unwrap());
+
+    processors.set_target_os(TargetOs::Windows);
+    let actions = processors.plan(&libraries, &root.join("client.jar"), &root, None, &mut source)?;
+    let Some(PlannedAction::Extract { target, .. }) = actions.iter().find(|action| matches!(action, PlannedAction::Extract { .. })) else {
+      panic!("The patch isn't extracted: {actions:?}");
+    };
+    assert!(target.to_str().unwrap().ends_with("data\\client.lzma"));
+    let Some(PlannedAction::RunProcessor { command, .. }) = actions.last() else {
+      panic!("The processor doesn't run: {actions:?}");
+    };
+    assert_eq!(command[3].split(';').count(), 2);
+
+    processors.set_target_os(TargetOs::Linux);
+    let Some(PlannedAction::RunProcessor { command, .. }) = processors.plan(&libraries, &root.join("client.jar"), &root, None, &mut source)?.pop() else {
+      panic!("The processor doesn't run");
+    };
+    assert_eq!(command[3].split(':').count(), 2);
+    Ok(())
+  }
+
+  #[test]
+  fn test_format_command() {
+    use forge_installer_profile::v2::format_command;
+    let args = ["-cp", "C:/Program Files/lib.jar", "a.Main", "--name", "say \"hi\"", ""].map(String::from);
+    assert_eq!(format_command("java", &args), r#"java -cp "C:/Program Files/lib.jar" a.Main --name "say \"hi\"" """#);
+  }
+
+  #[test]
+  fn test_replace_tokens() {
+    use forge_installer_profile::tokens::replace_tokens;
+    let tokens = HashMap::from([
+      ("SIDE".to_string(), "client".to_string()),
+      ("MOJMAPS".to_string(), String::new()),
+      ("KEY}".to_string(), "braced".to_string()),
+    ]);
+    assert_eq!(replace_tokens(&tokens, "--side={SIDE}").unwrap(), "--side=client");
+    assert_eq!(replace_tokens(&tokens, "{MOJMAPS}").unwrap(), "");
+    assert_eq!(replace_tokens(&tokens, "'{SIDE}'").unwrap(), "{SIDE}");
+    assert_eq!(replace_tokens(&tokens, "\\{SIDE\\}").unwrap(), "{SIDE}");
+    assert_eq!(replace_tokens(&tokens, "{KEY\\}}").unwrap(), "braced");
+    assert_eq!(replace_tokens(&tokens, "'it\\'s'").unwrap(), "it's");
+    assert_eq!(replace_tokens(&tokens, "ünïcödé {SIDE}").unwrap(), "ünïcödé client");
+    let tokens = HashMap::from([("ROOT".to_string(), "C:/Users/Jérôme/AppData/.minecraft".to_string())]);
+    assert_eq!(replace_tokens(&tokens, "{ROOT}/libraries").unwrap(), "C:/Users/Jérôme/AppData/.minecraft/libraries");
+    assert_eq!(replace_tokens(&tokens, "'é'{ROOT}sé").unwrap(), "éC:/Users/Jérôme/AppData/.minecraftsé");
+
+    assert!(replace_tokens(&tokens, "{SIDE").unwrap_err().contains("Unclosed {"));
+    assert!(replace_tokens(&tokens, "'literal").unwrap_err().contains("Unclosed '"));
+    assert!(replace_tokens(&tokens, "trailing\\").unwrap_err().contains("Bad escape"));
+    assert!(replace_tokens(&tokens, "{SIDE\\").unwrap_err().contains("Bad escape"));
+    assert!(replace_tokens(&tokens, "{MISSING}").unwrap_err().contains("Missing Key: MISSING"));
+  }
+
+  #[test]
+  fn test_directory_source() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TestDir::new("forge_directory_source");
+    fs::create_dir_all(dir.join("data"))?;
+    fs::write(dir.join("install_profile.json"), profile_json(serde_json::json!({})))?;
+    fs::write(
+      dir.join("version.json"),
+      r#"{
+        "id": "1.20.1-forge-47.2.0", "time": "2023-08-16T14:32:35+00:00", "releaseTime": "2023-08-16T14:32:35+00:00",
+        "type": "release", "mainClass": "cpw.mods.bootstraplauncher.BootstrapLauncher", "inheritsFrom": "1.20.1", "libraries": []
+      }"#
+    )?;
+    fs::write(dir.join("data").join("client.lzma"), b"patch")?;
+
+    let mut source = ForgeInstallProfileSource::<File>::Directory(dir.clone());
+    let profile = source.read_profile()?;
+    assert_eq!(profile.get_version_id(), "1.20.1-forge-47.2.0");
+    assert_eq!(profile.get_version_json(&mut source)?.inherits_from.as_deref(), Some("1.20.1"));
+    let target = dir.join("extracted").join("client.lzma");
+    download_utils::extract_file("/data/client.lzma", &target, &mut source)?;
+    assert_eq!(fs::read(&target)?, b"patch");
+    assert!(download_utils::extract_file("/data/server.lzma", &target, &mut source).is_err());
+
+    let mut missing = ForgeInstallProfileSource::<File>::Directory(dir.join("missing"));
+    assert!(matches!(missing.read_profile(), Err(forge_client_install::ForgeInstallError::ProfileNotFound(_))));
+    Ok(())
+  }
+
+  #[test]
+  fn test_nested_version_json() -> Result<(), Box<dyn std::error::Error>> {
+    let profile = |json: &str| {
+      ForgeInstallerProfile::from_reader(Cursor::new(profile_json(serde_json::json!({ "json": json }))))
+    };
+    let version_json = |id: &str| {
+      format!(
+        r#"{{ "id": "{id}", "time": "2023-08-16T14:32:35+00:00", "releaseTime": "2023-08-16T14:32:35+00:00", "type": "release", "mainClass": "a.Main", "libraries": [] }}"#
+      )
+    };
+    let mut installer = Cursor::new(vec![]);
+    let mut writer = zip::ZipWriter::new(&mut installer);
+    writer.start_file("version.json", zip::write::FileOptions::default())?;
+    writer.write_all(version_json("root").as_bytes())?;
+    writer.start_file("maven/net/minecraftforge/forge/1.20.1-47.2.0/version.json", zip::write::FileOptions::default())?;
+    writer.write_all(version_json("nested").as_bytes())?;
+    writer.finish()?;
+    drop(writer);
+    let mut source = ForgeInstallProfileSource::Zip(ZipArchive::new(installer)?);
+
+    let nested = profile("/maven/net/minecraftforge/forge/1.20.1-47.2.0/version.json")?;
+    assert_eq!(nested.get_version_json(&mut source)?.id, "nested");
+    assert_eq!(profile("/version.json")?.get_version_json(&mut source)?.id, "root");
+    // Not at its full path, so it's found by its file name
+    assert_eq!(profile("/data/version.json")?.get_version_json(&mut source)?.id, "root");
+    // Neither, so it's `version.json` at the root
+    assert_eq!(profile("/profile.json")?.get_version_json(&mut source)?.id, "root");
+
+    // Only under META-INF, found by scanning for a json that parses, skipping the ones that don't
+    let mut installer = Cursor::new(vec![]);
+    let mut writer = zip::ZipWriter::new(&mut installer);
+    writer.start_file("META-INF/forge/a-notes.json", zip::write::FileOptions::default())?;
+    writer.write_all(br#"{ "not": "a version" }"#)?;
+    writer.start_file("META-INF/forge/version-1.20.1.json", zip::write::FileOptions::default())?;
+    writer.write_all(version_json("meta-inf").as_bytes())?;
+    writer.finish()?;
+    drop(writer);
+    let mut source = ForgeInstallProfileSource::Zip(ZipArchive::new(installer)?);
+    assert_eq!(profile("/profile.json")?.get_version_json(&mut source)?.id, "meta-inf");
+
+    let mut source = empty_installer();
+    let err = profile("/data/profile.json")?.get_version_json(&mut source).unwrap_err();
+    assert_eq!(err.to_string(), "No version json in the installer, checked: data/profile.json, profile.json, version.json");
+    Ok(())
+  }
+
+  #[test]
+  fn test_logo() -> Result<(), Box<dyn std::error::Error>> {
+    let profile = ForgeInstallerProfile::from_reader(
+      Cursor::new(
+        profile_json(serde_json::json!({
+          "icon": "data:image/png;base64,iVBORw0KGgo=", "welcome": "Welcome to the simple Forge installer."
+        }))
+      )
+    )?;
+    assert_eq!(profile.get_welcome(), "Welcome to the simple Forge installer.");
+    let mut installer = Cursor::new(vec![]);
+    let mut writer = zip::ZipWriter::new(&mut installer);
+    writer.start_file("big_logo.png", zip::write::FileOptions::default())?;
+    writer.write_all(b"\x89PNG")?;
+    writer.finish()?;
+    drop(writer);
+    assert_eq!(profile.get_logo_bytes(&mut ForgeInstallProfileSource::Zip(ZipArchive::new(installer)?)), Some(b"\x89PNG".to_vec()));
+    assert_eq!(profile.get_logo_bytes(&mut ForgeInstallProfileSource::<File>::Directory(temp_dir().join("forge_no_logo"))), None);
+    Ok(())
+  }
+
+  #[test]
+  fn test_install_lock() -> Result<(), Box<dyn std::error::Error>> {
+    use forge_client_install::{ ForgeInstallError, InstallLock };
+    let mc_dir = TestDir::new("forge_install_lock");
+    fs::create_dir_all(&mc_dir)?;
+    let lock = InstallLock::acquire(&mc_dir)?;
+    assert!(matches!(InstallLock::acquire(&mc_dir), Err(ForgeInstallError::Locked(_))));
+    drop(lock);
+    assert!(!mc_dir.join(InstallLock::FILE_NAME).exists());
+    drop(forge_client_install::InstallLock::acquire(&mc_dir)?);
+    Ok(())
+  }
+
+  #[test]
+  fn test_copy_and_strip() -> Result<(), Box<dyn std::error::Error>> {
+    let root = TestDir::new("forge_copy_and_strip");
+    fs::create_dir_all(&root)?;
+    let mut writer = zip::ZipWriter::new(File::create(root.join("client.jar"))?);
+    let stored = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for name in ["META-INF/MANIFEST.MF", "META-INF/MOJANGCS.SF", "META-INF/MOJANGCS.RSA", "META-INF/SIG-MOJANG", "META-INF/services/a.Service", "a/Main.class"] {
+      writer.start_file(name, stored)?;
+      writer.write_all(name.as_bytes())?;
+    }
+    writer.finish()?;
+
+    forge_client_install::copy_and_strip(&root.join("client.jar"), &root.join("stripped.jar"))?;
+    let mut stripped = ZipArchive::new(File::open(root.join("stripped.jar"))?)?;
+    let mut names = stripped.file_names().collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, vec!["META-INF/MANIFEST.MF", "META-INF/services/a.Service", "a/Main.class"]);
+    assert_eq!(stripped.by_name("a/Main.class")?.compression(), zip::CompressionMethod::Stored);
+    Ok(())
+  }
+
+  #[test]
+  fn test_profile_with_bom() -> Result<(), Box<dyn std::error::Error>> {
+    let json = profile_json(serde_json::json!({}));
+    let profile = ForgeInstallerProfile::from_reader(Cursor::new(format!("\u{feff}\n  {json}")))?;
+    assert_eq!(profile.get_version_id(), "1.20.1-forge-47.2.0");
+    Ok(())
+  }
+
+  #[test]
+  fn test_fetch_version_json() -> Result<(), Box<dyn std::error::Error>> {
+    let mut installer = Cursor::new(vec![]);
+    let mut writer = zip::ZipWriter::new(&mut installer);
+    writer.start_file("install_profile.json", zip::write::FileOptions::default())?;
+    writer.write_all(profile_json(serde_json::json!({})).as_bytes())?;
+    writer.start_file("version.json", zip::write::FileOptions::default())?;
+    writer.write_all(br#"{ "id": "1.20.1-forge-47.2.0", "inheritsFrom": "1.20.1", "type": "release", "mainClass": "a.Main" }"#)?;
+    writer.finish()?;
+    drop(writer);
+
+    let (profile, version) = forge_installer_profile::fetch_version_json(installer.get_ref())?;
+    assert_eq!(profile.get_minecraft(), "1.20.1");
+    assert_eq!(version.id, "1.20.1-forge-47.2.0");
+    assert_eq!(version.inherits_from.as_deref(), Some("1.20.1"));
+    assert!(forge_installer_profile::fetch_version_json(b"not a zip").is_err());
+    Ok(())
+  }
+
+  #[test]
+  fn test_install_without_processors() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TestDir::new("forge_no_processors_installer");
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("install_profile.json"), profile_json(serde_json::json!({})))?;
+    fs::write(dir.join("version.json"), r#"{ "id": "1.20.1-forge-47.2.0", "inheritsFrom": "1.20.1", "type": "release", "mainClass": "a.Main" }"#)?;
+
+    // Nothing to process, so the missing java doesn't matter
+    let install = forge_client_install::ForgeClientInstallBuilder::new().java_path(dir.join("no-java")).build_from_dir(dir.clone())?;
+    assert!(install.get_java_version().is_none());
+    assert!(install.get_processor_checksums().is_empty());
+
+    // V1 profiles never run processors
+    fs::write(dir.join("install_profile.json"), LEGACY_PROFILE_1_6_4)?;
+    let install = forge_client_install::ForgeClientInstallBuilder::new().java_path(dir.join("no-java")).build_from_dir(dir.clone())?;
+    assert!(install.get_java_version().is_none());
+    Ok(())
+  }
+
+  #[test]
+  fn test_minecraft_version_override() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = TestDir::new("forge_minecraft_version_override");
+    fs::create_dir_all(&dir)?;
+    let profile = profile_json(serde_json::json!({}));
+    fs::write(dir.join("install_profile.json"), &profile)?;
+    fs::write(dir.join("version.json"), r#"{ "id": "1.20.1-forge-47.2.0", "inheritsFrom": "1.20.1", "type": "release", "mainClass": "a.Main" }"#)?;
+    let install = forge_client_install::ForgeClientInstallBuilder::new().minecraft_version("1.20.2").build_from_dir(dir.clone())?;
+    assert_eq!(install.get_minecraft_version(), "1.20.2");
+    assert_eq!(install.get_installer_profile().get_minecraft(), "1.20.1");
+
+    let mut processors = post_processors::PostProcessors::new(std::sync::Arc::new(ForgeInstallerProfile::from_reader(Cursor::new(profile))?), true, PathBuf::from("java"))?;
+    assert_eq!(processors.get_minecraft_version(), "1.20.1");
+    processors.set_minecraft_version(Some("1.20.2".to_string()));
+    assert_eq!(processors.get_minecraft_version(), "1.20.2");
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn test_supplied_vanilla_version() -> Result<(), Box<dyn std::error::Error>> {
+    use tokio::{ io::{ AsyncReadExt, AsyncWriteExt }, net::TcpListener };
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/client.jar", listener.local_addr()?);
+    let server = tokio::spawn(async move {
+      let (mut socket, _) = listener.accept().await?;
+      let mut request = vec![0; 4096];
+      let _ = socket.read(&mut request).await?;
+      socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\nConnection: close\r\n\r\nclient jar!").await?;
+      socket.shutdown().await
+    });
+
+    let dir = TestDir::new("forge_supplied_vanilla_version");
+    fs::create_dir_all(&dir)?;
+    fs::write(dir.join("install_profile.json"), profile_json(serde_json::json!({})))?;
+    fs::write(dir.join("version.json"), r#"{ "id": "1.20.1-forge-47.2.0", "inheritsFrom": "1.20.1", "type": "release", "mainClass": "a.Main" }"#)?;
+    let sha1 = Sha1Sum::from_reader(&mut Cursor::new(b"client jar!"))?;
+    let vanilla = serde_json::json!({ "id": "1.20.1", "downloads": { "client": { "url": url, "sha1": sha1.to_string() } } });
+
+    // The manifest isn't fetched, the client comes from the supplied url
+    let install = forge_client_install::ForgeClientInstallBuilder::new().vanilla_version_json(vanilla).build_from_dir(dir.clone())?;
+    let client_jar = install.download_vanilla_client_jar(&dir.join("versions")).await?;
+    server.await??;
+    assert_eq!(fs::read(&client_jar)?, b"client jar!");
+    Ok(())
+  }
+
+  #[test]
+  fn test_invalid_profile() {
+    let result = ForgeInstallerProfile::from_reader(Cursor::new("{ \"not\": \"a profile\" }"));
+    assert!(matches!(result, Err(forge_client_install::ForgeInstallError::InvalidProfile(_))));
+    let result = ForgeInstallerProfile::from_reader(Cursor::new("{ \"spec\": 1 }"));
+    assert!(matches!(result, Err(forge_client_install::ForgeInstallError::InvalidProfile(err)) if err.starts_with("V2 profile")));
+  }
+
   #[tokio::test]
   async fn test_parser() -> Result<(), Box<dyn std::error::Error>> {
     let cache_folder = std::env::temp_dir().join("forge_cache_versions");
     fs::create_dir_all(&cache_folder)?;
 
-    let versions = ForgeVersionHandler::new().await?;
+    let versions = ForgeVersionHandler::new(Loader::Forge).await?;
     let recommended_versions: Vec<String> = versions
       .get_recommended_versions()
       .iter()
@@ -248,14 +2143,14 @@ mod tests {
     for versions in forge_versions {
       let futures = versions
         .into_iter()
-        .map(|ver| process_version(&ver, &cache_folder))
+        .map(|ver| process_version(ver, &cache_folder))
         .collect::<Vec<_>>();
       join_all(futures).await;
     }
     Ok(())
   }
 
-  async fn process_version(full_forge_version: &str, cache_folder: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+  async fn process_version(full_forge_version: &str, cache_folder: &Path) -> Result<(), Box<dyn std::error::Error>> {
     let artifact = Artifact::try_from(format!("net.minecraftforge:forge:{full_forge_version}:installer"))?;
 
     let path = &cache_folder.join(artifact.get_file());
@@ -1,31 +1,62 @@
-use log::info;
-use zip::ZipArchive;
+use log::{ info, warn };
 use std::{
-  collections::HashMap,
+  collections::{ HashMap, HashSet },
   env,
   error::Error,
   fs::{ self, create_dir_all },
   io::{ Read, Seek },
-  path::{ PathBuf, MAIN_SEPARATOR_STR },
+  path::{ Path, PathBuf },
+  process,
   sync::Arc,
+  time::{ SystemTime, UNIX_EPOCH },
   ops::Deref,
 };
 
 use crate::{
   Artifact,
-  forge_client_install::ForgeInstallError,
-  forge_installer_profile::{ v2::ForgeInstallerProfileV2, ForgeInstallerProfile },
+  Sha1Sum,
+  forge_client_install::{ CancellationToken, ForgeInstallError, PlannedAction },
+  forge_installer_profile::{ source::ForgeInstallProfileSource, v2::ForgeInstallerProfileV2, ForgeInstallerProfile },
   download_utils,
-  forge_installer_profile::{ v2::Processor, ForgeVersionLibrary },
+  forge_installer_profile::{ v2::{ read_main_class, JvmOptions, Processor, ProcessorEnv, ProcessorHook, ProcessorOutput, TargetArch, TargetOs }, ForgeVersionLibrary },
 };
 
 pub struct PostProcessors {
   profile: Arc<ForgeInstallerProfile>,
   java_path: PathBuf,
+  jvm_options: JvmOptions,
+  target_os: TargetOs,
+  target_arch: TargetArch,
   is_client: bool,
   has_tasks: bool,
   processors: Vec<Processor>,
   data: HashMap<String, String>,
+  temp_dir: PathBuf,
+  keep_temp_dir: bool,
+  minecraft_version: Option<String>,
+  on_before_processor: Option<ProcessorHook>,
+}
+
+/// Installer entries still to be extracted, with the path each one goes to
+type DataExtracts = Vec<(String, PathBuf)>;
+
+/// Removes the directory of an install when dropped, so it's gone however the processors end, including panics
+struct TempDirGuard {
+  path: PathBuf,
+  keep: bool,
+}
+
+impl Drop for TempDirGuard {
+  fn drop(&mut self) {
+    if !self.path.exists() {
+      return;
+    }
+    if self.keep {
+      info!("Keeping temporary directory {}", self.path.display());
+    } else if let Err(err) = fs::remove_dir_all(&self.path) {
+      warn!("Failed to remove temporary directory {}: {err}", self.path.display());
+    }
+  }
 }
 
 impl PostProcessors {
@@ -49,22 +80,124 @@ impl PostProcessors {
       Ok(Self {
         profile: arc_profile,
         java_path,
+        jvm_options: JvmOptions::default(),
+        target_os: TargetOs::current(),
+        target_arch: TargetArch::current(),
         is_client,
         data,
         has_tasks,
         processors,
+        temp_dir: env::temp_dir().join("forge_installer"),
+        keep_temp_dir: false,
+        minecraft_version: None,
+        on_before_processor: None,
       })
     } else {
       Err(forge_err!("Not a v2 profile."))?
     }
   }
 
+  /// Token values passed to the processors, files are only extracted once [`Self::process`] ran
+  pub fn get_data(&self) -> &HashMap<String, String> {
+    &self.data
+  }
+
+  pub fn get_temp_dir(&self) -> &PathBuf {
+    &self.temp_dir
+  }
+
+  /// Directory the data files are extracted to. Every install uses its own subdirectory, which is
+  /// removed once the processors are done.
+  pub fn set_temp_dir(&mut self, temp_dir: PathBuf) {
+    self.temp_dir = temp_dir;
+  }
+
+  pub fn get_keep_temp_dir(&self) -> bool {
+    self.keep_temp_dir
+  }
+
+  /// Leaves the extracted data files in place after [`Self::process`], to debug a failing processor
+  pub fn set_keep_temp_dir(&mut self, keep_temp_dir: bool) {
+    self.keep_temp_dir = keep_temp_dir;
+  }
+
+  /// Value of the `MINECRAFT_VERSION` token, the profile's minecraft version unless overridden
+  pub fn get_minecraft_version(&self) -> String {
+    self.minecraft_version.clone().unwrap_or_else(|| self.get_inner_profile().minecraft.clone())
+  }
+
+  pub fn set_minecraft_version(&mut self, minecraft_version: Option<String>) {
+    self.minecraft_version = minecraft_version;
+  }
+
+  pub fn get_jvm_options(&self) -> &JvmOptions {
+    &self.jvm_options
+  }
+
+  pub fn set_jvm_options(&mut self, jvm_options: JvmOptions) {
+    self.jvm_options = jvm_options;
+  }
+
+  pub fn get_on_before_processor(&self) -> Option<&ProcessorHook> {
+    self.on_before_processor.as_ref()
+  }
+
+  /// Called before java runs each processor whose outputs aren't cached, see [`ProcessorDecision`](crate::forge_installer_profile::v2::ProcessorDecision)
+  pub fn set_on_before_processor(&mut self, on_before_processor: Option<ProcessorHook>) {
+    self.on_before_processor = on_before_processor;
+  }
+
+  pub fn get_target_os(&self) -> &TargetOs {
+    &self.target_os
+  }
+
+  /// Platform the processors' classpath and data file paths are written for, the host by default
+  pub fn set_target_os(&mut self, target_os: TargetOs) {
+    self.target_os = target_os;
+  }
+
+  pub fn get_target_arch(&self) -> &TargetArch {
+    &self.target_arch
+  }
+
+  /// Architecture the rules of the profile libraries are resolved for, the host by default
+  pub fn set_target_arch(&mut self, target_arch: TargetArch) {
+    self.target_arch = target_arch;
+  }
+
+  /// Checksums the profile libraries declare for the jars on the processors' classpath, jars without
+  /// one are left out
+  pub fn get_processor_checksums(&self) -> HashMap<Artifact, Sha1Sum> {
+    let jars = self.processors
+      .iter()
+      .flat_map(|proc| [&proc.jar].into_iter().chain(&proc.classpath))
+      .collect::<HashSet<_>>();
+    self
+      .get_libraries()
+      .into_iter()
+      .filter_map(ForgeVersionLibrary::to_mojang)
+      .filter(|lib| jars.contains(&lib.name))
+      .filter_map(|lib| Some((lib.name.clone(), lib.downloads.artifact.as_ref()?.sha1.clone()?)))
+      .collect()
+  }
+
+  /// Profile libraries the processors run with, leaving out the ones whose rules exclude the target platform
   pub fn get_libraries(&self) -> Vec<&ForgeVersionLibrary> {
-    if self.has_tasks { self.get_inner_profile().get_libraries() } else { vec![] }
+    if !self.has_tasks {
+      return vec![];
+    }
+    self
+      .get_inner_profile()
+      .get_libraries()
+      .into_iter()
+      .filter(|lib| lib.to_mojang().is_none_or(|lib| lib.is_allowed(&self.target_os, &self.target_arch)))
+      .collect()
   }
 
+  /// Steps of [`Self::process`] for a progress bar: the profile libraries, the processors and the data
+  /// entries, 0 when there's nothing to process
   pub fn get_task_count(&self) -> usize {
-    if self.has_tasks {
+    if !self.has_tasks {
       return 0;
     }
     self.get_inner_profile().get_libraries().len() + self.processors.len() + self.get_inner_profile().get_data(self.is_client).len()
@@ -72,67 +205,147 @@ impl PostProcessors {
 
   pub async fn process(
     &mut self,
-    libraries_dir: &PathBuf,
-    client_jar: &PathBuf,
-    mc_dir: &PathBuf,
-    installer_path: &PathBuf,
-    archive: &mut ZipArchive<impl Read + Seek>
-  ) -> Result<(), Box<dyn Error>> {
+    libraries_dir: &Path,
+    client_jar: &Path,
+    mc_dir: &Path,
+    installer_path: Option<&PathBuf>,
+    source: &mut ForgeInstallProfileSource<impl Read + Seek>,
+    cancel: &CancellationToken
+  ) -> Result<Vec<ProcessorOutput>, Box<dyn Error>> {
+    let guard = TempDirGuard { path: self.get_install_temp_dir(), keep: self.keep_temp_dir };
+    let temp = &guard.path;
+    let (data, extracts) = self.resolve_data(temp, libraries_dir, client_jar, mc_dir, installer_path, source)?;
     if !self.data.is_empty() {
       let mut err = String::new();
-      let temp = env::temp_dir().join("forge_installer");
-      let _ = fs::remove_dir_all(&temp);
-      create_dir_all(&temp)?;
+      create_dir_all(temp)?;
       info!("Created Temporary Directory: {}", temp.display());
-      let steps = self.data.len();
-      let mut i = 1;
-      for (key, value) in &self.data.clone() {
-        info!("Processing library {i}/{steps}");
-        i += 1;
-        if value.starts_with('[') && value.ends_with(']') {
-          let inner_value = value[1..value.len() - 1].to_string();
-          let artifact = Artifact::try_from(inner_value)?;
-          let local_path = artifact.get_local_path(&libraries_dir).to_str().unwrap().to_string();
-          self.data.insert(key.clone(), local_path);
-          continue;
-        }
-        if value.starts_with('\'') && value.ends_with('\'') {
-          let inner_value = value[1..value.len() - 1].to_string();
-          self.data.insert(key.clone(), inner_value);
-          continue;
-        }
-        let target = temp.join(value.replace("/", MAIN_SEPARATOR_STR));
+      let steps = extracts.len();
+      for (i, (value, target)) in extracts.iter().enumerate() {
+        cancel.check()?;
+        info!("Processing library {}/{steps}", i + 1);
         info!("  Extracting: {} to {}", &value, target.display());
-        if let Err(e) = download_utils::extract_file(&value, &target, archive) {
+        if let Err(e) = download_utils::extract_file(value, target, source) {
           info!("Failed to extract {value}: {e}");
           err.push_str(&format!("\n  {}", &value));
         }
-
-        let target = target.to_str().unwrap().to_string();
-        self.data.insert(key.clone(), target);
       }
       if !err.is_empty() {
         Err(forge_err!("Failed to extract files from archive: {err}"))?;
       }
     }
-    self.data.insert("SIDE".to_string(), (if self.is_client { "client" } else { "server" }).to_string());
-    self.data.insert("MINECRAFT_JAR".to_string(), client_jar.to_str().unwrap().to_string());
-    self.data.insert("MINECRAFT_VERSION".to_string(), self.get_inner_profile().minecraft.clone());
-    self.data.insert("ROOT".to_string(), mc_dir.to_str().unwrap().to_string());
-    self.data.insert("INSTALLER".to_string(), installer_path.to_str().unwrap().to_string());
-    self.data.insert("LIBRARY_DIR".to_string(), libraries_dir.to_str().unwrap().to_string());
+    self.data = data;
     let mut progress = 1;
     if self.processors.len() == 1 {
       info!("Building Processor");
     } else {
       info!("Building Processors");
     }
+    let env = ProcessorEnv {
+      java_path: &self.java_path,
+      jvm_options: &self.jvm_options,
+      os: &self.target_os,
+      on_before: self.on_before_processor.as_ref(),
+    };
+    let mut outputs = vec![];
     for proc in &self.processors {
+      cancel.check()?;
       info!("Building processor {progress}/{}...", self.processors.len());
       progress += 1;
       info!("===============================================================================");
-      proc.process(&self.data, libraries_dir, &self.java_path)?;
+      outputs.push(proc.process(&self.data, libraries_dir, &env, cancel)?);
+    }
+    Ok(outputs)
+  }
+
+  /// Unique per install, so concurrent installs don't overwrite each other's data files
+  fn get_install_temp_dir(&self) -> PathBuf {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    self.temp_dir.join(format!("{}-{}-{nanos}", self.get_inner_profile().version, process::id()))
+  }
+
+  /// Everything [`Self::process`] would do, without touching the disk: the data files it would extract
+  /// and the java command line of every processor. Processor jars that aren't on disk yet can't
+  /// be read, so their main class is shown as `<Main-Class of group:name:version>`.
+  pub fn plan(
+    &self,
+    libraries_dir: &Path,
+    client_jar: &Path,
+    mc_dir: &Path,
+    installer_path: Option<&PathBuf>,
+    source: &mut ForgeInstallProfileSource<impl Read + Seek>
+  ) -> Result<Vec<PlannedAction>, Box<dyn Error>> {
+    let temp = &self.get_install_temp_dir();
+    let (data, extracts) = self.resolve_data(temp, libraries_dir, client_jar, mc_dir, installer_path, source)?;
+    let mut actions = vec![];
+    if !self.data.is_empty() {
+      actions.push(PlannedAction::CreateDir(temp.clone()));
+    }
+    for (entry, target) in extracts {
+      actions.push(PlannedAction::Extract { entry, target });
+    }
+    for proc in &self.processors {
+      let jar = proc.jar.get_local_path(libraries_dir);
+      let main_class = match jar.is_file() {
+        true => read_main_class(&jar)?,
+        false => format!("<Main-Class of {}>", proc.jar.get_descriptor()),
+      };
+      let mut command = vec![self.java_path.to_str().unwrap().to_string()];
+      command.extend(self.jvm_options.args.iter().cloned());
+      command.extend(proc.get_java_args(&main_class, &data, libraries_dir, &self.target_os)?);
+      actions.push(PlannedAction::RunProcessor { jar: proc.jar.clone(), command });
+    }
+    Ok(actions)
+  }
+
+  /// Resolves the data tokens for an install using `temp`, returning them with the installer entries
+  /// that still have to be extracted for the file values
+  fn resolve_data(
+    &self,
+    temp: &Path,
+    libraries_dir: &Path,
+    client_jar: &Path,
+    mc_dir: &Path,
+    installer_path: Option<&PathBuf>,
+    source: &mut ForgeInstallProfileSource<impl Read + Seek>
+  ) -> Result<(HashMap<String, String>, DataExtracts), Box<dyn Error>> {
+    let mut data = self.data.clone();
+    let mut extracts = vec![];
+    for (key, value) in &self.data {
+      // Entries only used by the other side are empty, they stay in the data so tokens referencing them
+      // resolve to an empty string
+      if value.is_empty() {
+        continue;
+      }
+      if value.starts_with('[') && value.ends_with(']') {
+        let inner_value = value[1..value.len() - 1].to_string();
+        let artifact = Artifact::try_from(inner_value)?;
+        let local_path = artifact.get_local_path(libraries_dir).to_str().unwrap().to_string();
+        data.insert(key.clone(), local_path);
+        continue;
+      }
+      if value.starts_with('\'') && value.ends_with('\'') {
+        let inner_value = value[1..value.len() - 1].to_string();
+        data.insert(key.clone(), inner_value);
+        continue;
+      }
+      // Anything that isn't an entry of the installer (like a bare sha1) can't be extracted and is passed as is
+      if source.by_name(value.trim_start_matches('/')).is_err() {
+        warn!("Data {key} isn't an artifact, a literal or an installer entry, passing it as is: {value}");
+        continue;
+      }
+      // Values are absolute inside the installer, joined as is they would replace `temp`
+      let target = temp.join(value.trim_start_matches('/').replace("/", self.target_os.get_path_separator()));
+      data.insert(key.clone(), target.to_str().unwrap().to_string());
+      extracts.push((value.clone(), target));
+    }
+    data.insert("SIDE".to_string(), (if self.is_client { "client" } else { "server" }).to_string());
+    data.insert("MINECRAFT_JAR".to_string(), client_jar.to_str().unwrap().to_string());
+    data.insert("MINECRAFT_VERSION".to_string(), self.get_minecraft_version());
+    data.insert("ROOT".to_string(), mc_dir.to_str().unwrap().to_string());
+    if let Some(installer_path) = installer_path {
+      data.insert("INSTALLER".to_string(), installer_path.to_str().unwrap().to_string());
     }
-    Ok(())
+    data.insert("LIBRARY_DIR".to_string(), libraries_dir.to_str().unwrap().to_string());
+    Ok((data, extracts))
   }
 }
@@ -0,0 +1,10 @@
+//! The types needed for an install, so `use forge_downloader::prelude::*;` is enough for most consumers
+
+pub use crate::{
+  Artifact,
+  Sha1Sum,
+  download_utils::{ forge::ForgeVersionHandler, DownloadConfig },
+  forge_client_install::{ ForgeClientInstall, ForgeClientInstallBuilder, ForgeInstallError, InstallReport, CancellationToken },
+  forge_installer_profile::ForgeInstallerProfile,
+  post_processors::PostProcessors,
+};